@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE tasks (
+  id VARCHAR PRIMARY KEY,
+  document_id VARCHAR NOT NULL,
+  filename VARCHAR NOT NULL,
+  file_path VARCHAR NOT NULL,
+  ocr_mode VARCHAR NOT NULL,
+  ocr_languages VARCHAR NOT NULL,
+  status VARCHAR NOT NULL DEFAULT 'queued',
+  error TEXT,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('tasks');
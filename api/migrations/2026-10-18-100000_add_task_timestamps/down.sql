@@ -0,0 +1,3 @@
+ALTER TABLE tasks
+  DROP COLUMN started_at,
+  DROP COLUMN finished_at;
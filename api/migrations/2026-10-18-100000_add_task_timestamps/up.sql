@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE tasks
+  ADD COLUMN started_at TIMESTAMP,
+  ADD COLUMN finished_at TIMESTAMP;
@@ -0,0 +1,5 @@
+DROP INDEX tasks_checksum_idx;
+ALTER TABLE tasks DROP COLUMN checksum;
+
+DROP INDEX documents_checksum_idx;
+ALTER TABLE documents DROP COLUMN checksum;
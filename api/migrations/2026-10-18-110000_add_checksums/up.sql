@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE documents ADD COLUMN checksum VARCHAR;
+CREATE UNIQUE INDEX documents_checksum_idx ON documents (checksum);
+
+ALTER TABLE tasks ADD COLUMN checksum VARCHAR;
+CREATE INDEX tasks_checksum_idx ON tasks (checksum);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE tasks ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
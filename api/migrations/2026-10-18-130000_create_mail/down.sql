@@ -0,0 +1,2 @@
+DROP TABLE mail_rules;
+DROP TABLE mail_accounts;
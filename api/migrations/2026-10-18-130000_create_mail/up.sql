@@ -0,0 +1,30 @@
+-- Your SQL goes here
+CREATE TABLE mail_accounts (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  host VARCHAR NOT NULL,
+  port INTEGER NOT NULL DEFAULT 993,
+  -- `ssl` for IMAPS, `none` for plain IMAP (e.g. a local test server)
+  security VARCHAR NOT NULL DEFAULT 'ssl',
+  username VARCHAR NOT NULL,
+  password VARCHAR NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE mail_rules (
+  id SERIAL PRIMARY KEY,
+  account_id INTEGER NOT NULL REFERENCES mail_accounts (id) ON DELETE CASCADE,
+  name VARCHAR NOT NULL,
+  folder VARCHAR NOT NULL DEFAULT 'INBOX',
+  filter_from VARCHAR,
+  filter_subject VARCHAR,
+  max_age_days INTEGER,
+  -- `attachments` or `body`
+  scope VARCHAR NOT NULL DEFAULT 'attachments',
+  -- `mark_read`, `flag`, `move` or `delete`
+  action VARCHAR NOT NULL DEFAULT 'mark_read',
+  -- the target folder of `move`
+  action_parameter VARCHAR,
+  tags TEXT[] NOT NULL DEFAULT '{}',
+  enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
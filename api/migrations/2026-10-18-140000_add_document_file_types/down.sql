@@ -0,0 +1,2 @@
+ALTER TABLE documents DROP COLUMN extension;
+ALTER TABLE documents DROP COLUMN mime_type;
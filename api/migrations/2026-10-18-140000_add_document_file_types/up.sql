@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE documents ADD COLUMN mime_type VARCHAR NOT NULL DEFAULT 'application/pdf';
+ALTER TABLE documents ADD COLUMN extension VARCHAR NOT NULL DEFAULT 'pdf';
@@ -0,0 +1,6 @@
+DROP TABLE mails;
+
+ALTER TABLE tasks DROP COLUMN parent_id;
+
+DROP INDEX documents_parent_id_idx;
+ALTER TABLE documents DROP COLUMN parent_id;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE documents ADD COLUMN parent_id VARCHAR REFERENCES documents (id) ON DELETE SET NULL;
+CREATE INDEX documents_parent_id_idx ON documents (parent_id);
+
+ALTER TABLE tasks ADD COLUMN parent_id VARCHAR;
+
+CREATE TABLE mails (
+  document_id VARCHAR PRIMARY KEY REFERENCES documents (id) ON DELETE CASCADE,
+  from_address VARCHAR,
+  to_address VARCHAR,
+  subject VARCHAR,
+  sent_at TIMESTAMP,
+  message_id VARCHAR
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- only kept until the task is finished
+ALTER TABLE tasks ADD COLUMN password VARCHAR;
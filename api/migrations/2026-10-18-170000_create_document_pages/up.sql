@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE document_pages (
+  document_id VARCHAR NOT NULL REFERENCES documents (id) ON DELETE CASCADE,
+  page_number INTEGER NOT NULL,
+  body TEXT NOT NULL,
+  PRIMARY KEY (document_id, page_number)
+);
+
+-- the pages of existing documents are separated by form feeds in their body, and the last one
+-- ends with one too, which would otherwise make an empty page after it
+INSERT INTO document_pages (document_id, page_number, body)
+SELECT documents.id, page.number, page.body
+FROM documents, unnest(string_to_array(regexp_replace(documents.body, E'\f$', ''), E'\f')) WITH ORDINALITY AS page (body, number);
@@ -0,0 +1,3 @@
+DROP INDEX documents_created_idx;
+ALTER TABLE documents DROP COLUMN added;
+ALTER TABLE documents DROP COLUMN created;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE documents ADD COLUMN created DATE;
+ALTER TABLE documents ADD COLUMN added TIMESTAMP NOT NULL DEFAULT NOW();
+CREATE INDEX documents_created_idx ON documents (created);
+
+-- existing documents were added when their upload was queued
+UPDATE documents SET added = tasks.created_at FROM tasks WHERE tasks.document_id = documents.id;
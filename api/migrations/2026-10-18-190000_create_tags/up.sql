@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE tags (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  color VARCHAR NOT NULL DEFAULT '#a6cee3',
+  parent_id INTEGER REFERENCES tags (id) ON DELETE CASCADE
+);
+
+-- names are unique among siblings, so a path like `Finance/Tax` names one tag
+CREATE UNIQUE INDEX tags_parent_id_name_idx ON tags (COALESCE(parent_id, 0), name);
+
+CREATE TABLE document_tags (
+  document_id VARCHAR NOT NULL REFERENCES documents (id) ON DELETE CASCADE,
+  tag_id INTEGER NOT NULL REFERENCES tags (id) ON DELETE CASCADE,
+  PRIMARY KEY (document_id, tag_id)
+);
+
+CREATE INDEX document_tags_tag_id_idx ON document_tags (tag_id);
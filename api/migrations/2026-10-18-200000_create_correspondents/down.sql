@@ -0,0 +1,4 @@
+DROP INDEX documents_correspondent_id_idx;
+ALTER TABLE documents DROP COLUMN correspondent_id;
+
+DROP TABLE correspondents;
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE correspondents (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  matching VARCHAR NOT NULL DEFAULT ''
+);
+
+ALTER TABLE documents ADD COLUMN correspondent_id INTEGER REFERENCES correspondents (id) ON DELETE SET NULL;
+CREATE INDEX documents_correspondent_id_idx ON documents (correspondent_id);
@@ -0,0 +1,4 @@
+DROP INDEX documents_document_type_id_idx;
+ALTER TABLE documents DROP COLUMN document_type_id;
+
+DROP TABLE document_types;
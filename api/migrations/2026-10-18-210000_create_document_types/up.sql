@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE document_types (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  matching VARCHAR NOT NULL DEFAULT ''
+);
+
+ALTER TABLE documents ADD COLUMN document_type_id INTEGER REFERENCES document_types (id) ON DELETE SET NULL;
+CREATE INDEX documents_document_type_id_idx ON documents (document_type_id);
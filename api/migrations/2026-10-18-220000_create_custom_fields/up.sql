@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TABLE custom_fields (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL,
+  data_type VARCHAR NOT NULL,
+  select_options TEXT[] NOT NULL DEFAULT '{}'
+);
+
+-- searches use the names as field names, ignoring case
+CREATE UNIQUE INDEX custom_fields_name_idx ON custom_fields (LOWER(name));
+
+-- one column per kind of value, the one matching the data type of the field is set
+CREATE TABLE custom_field_values (
+  document_id VARCHAR NOT NULL REFERENCES documents (id) ON DELETE CASCADE,
+  field_id INTEGER NOT NULL REFERENCES custom_fields (id) ON DELETE CASCADE,
+  value_text VARCHAR,
+  value_integer BIGINT,
+  value_monetary BIGINT,
+  value_date DATE,
+  value_boolean BOOLEAN,
+  value_document_id VARCHAR REFERENCES documents (id) ON DELETE CASCADE,
+  PRIMARY KEY (document_id, field_id)
+);
+
+CREATE INDEX custom_field_values_field_id_idx ON custom_field_values (field_id);
@@ -0,0 +1,9 @@
+ALTER TABLE document_types DROP COLUMN is_insensitive;
+ALTER TABLE document_types DROP COLUMN matching_algorithm;
+
+ALTER TABLE correspondents DROP COLUMN is_insensitive;
+ALTER TABLE correspondents DROP COLUMN matching_algorithm;
+
+ALTER TABLE tags DROP COLUMN is_insensitive;
+ALTER TABLE tags DROP COLUMN matching_algorithm;
+ALTER TABLE tags DROP COLUMN matching;
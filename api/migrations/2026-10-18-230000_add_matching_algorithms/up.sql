@@ -0,0 +1,14 @@
+-- Your SQL goes here
+ALTER TABLE tags ADD COLUMN matching VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tags ADD COLUMN matching_algorithm VARCHAR NOT NULL DEFAULT 'any';
+ALTER TABLE tags ADD COLUMN is_insensitive BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE correspondents ADD COLUMN matching_algorithm VARCHAR NOT NULL DEFAULT 'any';
+ALTER TABLE correspondents ADD COLUMN is_insensitive BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE document_types ADD COLUMN matching_algorithm VARCHAR NOT NULL DEFAULT 'any';
+ALTER TABLE document_types ADD COLUMN is_insensitive BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- until now the text was looked for as it is
+UPDATE correspondents SET matching_algorithm = 'exact' WHERE matching <> '';
+UPDATE document_types SET matching_algorithm = 'exact' WHERE matching <> '';
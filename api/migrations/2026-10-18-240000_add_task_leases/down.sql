@@ -0,0 +1,2 @@
+ALTER TABLE tasks DROP COLUMN heartbeat_at;
+ALTER TABLE tasks DROP COLUMN claimed_by;
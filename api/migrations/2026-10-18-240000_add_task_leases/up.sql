@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- the worker process running a task, and when it last said it still is
+ALTER TABLE tasks ADD COLUMN claimed_by VARCHAR;
+ALTER TABLE tasks ADD COLUMN heartbeat_at TIMESTAMP;
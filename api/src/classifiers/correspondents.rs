@@ -0,0 +1,109 @@
+// Who a document is from (or to), one per document.
+
+use std::collections::HashMap;
+
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use super::{Classifier, RuleFields};
+use crate::models::{Correspondent, CorrespondentChanges, NewCorrespondent};
+use crate::schema::{correspondents, documents};
+
+impl RuleFields for NewCorrespondent {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+impl RuleFields for CorrespondentChanges {
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+impl Classifier for Correspondent {
+    type New = NewCorrespondent;
+    type Changes = CorrespondentChanges;
+
+    const KIND: &'static str = "correspondent";
+
+    fn current_rule(&self) -> (&str, &str) {
+        (&self.matching, &self.matching_algorithm)
+    }
+
+    fn is_unchanged(changes: &CorrespondentChanges) -> bool {
+        changes.name.is_none()
+            && changes.matching.is_none()
+            && changes.matching_algorithm.is_none()
+            && changes.is_insensitive.is_none()
+    }
+
+    fn load_all(conn: &mut PgConnection) -> QueryResult<Vec<Self>> {
+        correspondents::table
+            .select(Correspondent::as_select())
+            .order(correspondents::name)
+            .load(conn)
+    }
+
+    fn document_counts(conn: &mut PgConnection) -> QueryResult<HashMap<i32, i64>> {
+        Ok(documents::table
+            .filter(documents::correspondent_id.is_not_null())
+            .group_by(documents::correspondent_id)
+            .select((documents::correspondent_id, diesel::dsl::count_star()))
+            .load::<(Option<i32>, i64)>(conn)?
+            .into_iter()
+            .filter_map(|(id, count)| Some((id?, count)))
+            .collect())
+    }
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn find(conn: &mut PgConnection, id: i32) -> QueryResult<Option<Self>> {
+        correspondents::table
+            .find(id)
+            .select(Correspondent::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    fn insert(conn: &mut PgConnection, new: &NewCorrespondent) -> QueryResult<Self> {
+        diesel::insert_into(correspondents::table)
+            .values(new)
+            .returning(Correspondent::as_returning())
+            .get_result(conn)
+    }
+
+    fn update(
+        conn: &mut PgConnection,
+        id: i32,
+        changes: &CorrespondentChanges,
+    ) -> QueryResult<Self> {
+        diesel::update(correspondents::table.find(id))
+            .set(changes)
+            .returning(Correspondent::as_returning())
+            .get_result(conn)
+    }
+
+    fn delete(conn: &mut PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(correspondents::table.find(id)).execute(conn)
+    }
+
+    fn documents(conn: &mut PgConnection, id: i32) -> QueryResult<Vec<String>> {
+        documents::table
+            .filter(documents::correspondent_id.eq(id))
+            .select(documents::id)
+            .load(conn)
+    }
+}
@@ -0,0 +1,109 @@
+// What kind of document it is, e.g. an invoice, one per document.
+
+use std::collections::HashMap;
+
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use super::{Classifier, RuleFields};
+use crate::models::{DocumentType, DocumentTypeChanges, NewDocumentType};
+use crate::schema::{document_types, documents};
+
+impl RuleFields for NewDocumentType {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+impl RuleFields for DocumentTypeChanges {
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+impl Classifier for DocumentType {
+    type New = NewDocumentType;
+    type Changes = DocumentTypeChanges;
+
+    const KIND: &'static str = "document type";
+
+    fn current_rule(&self) -> (&str, &str) {
+        (&self.matching, &self.matching_algorithm)
+    }
+
+    fn is_unchanged(changes: &DocumentTypeChanges) -> bool {
+        changes.name.is_none()
+            && changes.matching.is_none()
+            && changes.matching_algorithm.is_none()
+            && changes.is_insensitive.is_none()
+    }
+
+    fn load_all(conn: &mut PgConnection) -> QueryResult<Vec<Self>> {
+        document_types::table
+            .select(DocumentType::as_select())
+            .order(document_types::name)
+            .load(conn)
+    }
+
+    fn document_counts(conn: &mut PgConnection) -> QueryResult<HashMap<i32, i64>> {
+        Ok(documents::table
+            .filter(documents::document_type_id.is_not_null())
+            .group_by(documents::document_type_id)
+            .select((documents::document_type_id, diesel::dsl::count_star()))
+            .load::<(Option<i32>, i64)>(conn)?
+            .into_iter()
+            .filter_map(|(id, count)| Some((id?, count)))
+            .collect())
+    }
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn find(conn: &mut PgConnection, id: i32) -> QueryResult<Option<Self>> {
+        document_types::table
+            .find(id)
+            .select(DocumentType::as_select())
+            .first(conn)
+            .optional()
+    }
+
+    fn insert(conn: &mut PgConnection, new: &NewDocumentType) -> QueryResult<Self> {
+        diesel::insert_into(document_types::table)
+            .values(new)
+            .returning(DocumentType::as_returning())
+            .get_result(conn)
+    }
+
+    fn update(
+        conn: &mut PgConnection,
+        id: i32,
+        changes: &DocumentTypeChanges,
+    ) -> QueryResult<Self> {
+        diesel::update(document_types::table.find(id))
+            .set(changes)
+            .returning(DocumentType::as_returning())
+            .get_result(conn)
+    }
+
+    fn delete(conn: &mut PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(document_types::table.find(id)).execute(conn)
+    }
+
+    fn documents(conn: &mut PgConnection, id: i32) -> QueryResult<Vec<String>> {
+        documents::table
+            .filter(documents::document_type_id.eq(id))
+            .select(documents::id)
+            .load(conn)
+    }
+}
@@ -0,0 +1,253 @@
+// Tags, correspondents and document types: named, assigned to documents by hand or by a rule
+// matching the text of new ones (see `crate::matching`), and searched by. Correspondents and
+// types only differ in their table, so their endpoints are written once against `Classifier`.
+// Tags form a tree and have endpoints of their own, sharing the checks of the rule.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use diesel::{OptionalExtension, PgConnection, QueryResult};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::{AppState, matching, name_conflict, reindex};
+
+mod correspondents;
+mod document_types;
+pub mod tags;
+
+/// The name and matching rule of a new or changed tag, correspondent or type.
+pub trait RuleFields {
+    /// The name it gets, if it is given one.
+    fn name(&self) -> Option<&str>;
+
+    /// The algorithm, to be replaced with how it is stored, and the pattern.
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>);
+}
+
+/// A correspondent or document type, with the queries on its table.
+pub trait Classifier: Serialize + Sized + Send + 'static {
+    type New: RuleFields + DeserializeOwned + Send;
+    type Changes: RuleFields + DeserializeOwned + Send;
+
+    /// What it is called in messages, e.g. `document type`.
+    const KIND: &'static str;
+
+    /// The pattern and algorithm of its rule.
+    fn current_rule(&self) -> (&str, &str);
+
+    /// Whether the changes leave everything as it is, which makes an empty UPDATE diesel
+    /// refuses.
+    fn is_unchanged(changes: &Self::Changes) -> bool;
+
+    /// All of them, by name.
+    fn load_all(conn: &mut PgConnection) -> QueryResult<Vec<Self>>;
+
+    /// How many documents each one has, by id.
+    fn document_counts(conn: &mut PgConnection) -> QueryResult<HashMap<i32, i64>>;
+
+    fn id(&self) -> i32;
+
+    fn find(conn: &mut PgConnection, id: i32) -> QueryResult<Option<Self>>;
+
+    fn insert(conn: &mut PgConnection, new: &Self::New) -> QueryResult<Self>;
+
+    fn update(conn: &mut PgConnection, id: i32, changes: &Self::Changes) -> QueryResult<Self>;
+
+    fn delete(conn: &mut PgConnection, id: i32) -> QueryResult<usize>;
+
+    /// Ids of the documents it is assigned to.
+    fn documents(conn: &mut PgConnection, id: i32) -> QueryResult<Vec<String>>;
+}
+
+/// Check the matching rule of a tag, correspondent or type as it will be once changed, given
+/// the `current` pattern and algorithm of an existing one. The algorithm is stored in
+/// lowercase.
+pub fn validate_rule(
+    fields: &mut impl RuleFields,
+    current: Option<(&str, &str)>,
+) -> Result<(), (StatusCode, String)> {
+    let (algorithm, pattern) = fields.rule();
+
+    let effective_algorithm = algorithm
+        .as_deref()
+        .or(current.map(|(_, algorithm)| algorithm))
+        .unwrap_or("any");
+    let effective_pattern = pattern.or(current.map(|(pattern, _)| pattern));
+
+    let validated = matching::validate(effective_algorithm, effective_pattern)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    if algorithm.is_some() {
+        *algorithm = Some(validated);
+    }
+
+    Ok(())
+}
+
+fn validate<C: Classifier>(
+    fields: &mut impl RuleFields,
+    current: Option<(&str, &str)>,
+) -> Result<(), (StatusCode, String)> {
+    if fields.name().is_some_and(|name| name.trim().is_empty()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("A {} needs a name", C::KIND),
+        ));
+    }
+
+    validate_rule(fields, current)
+}
+
+fn not_found<C: Classifier>() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("No such {}", C::KIND))
+}
+
+/// A correspondent or type and how many documents it has.
+#[derive(Serialize)]
+pub struct View<C> {
+    #[serde(flatten)]
+    item: C,
+    document_count: i64,
+}
+
+pub async fn list<C: Classifier>(State(state): State<Arc<AppState>>) -> Json<Vec<View<C>>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let counts = C::document_counts(&mut conn).expect("Failed to count documents");
+    let all =
+        C::load_all(&mut conn).unwrap_or_else(|e| panic!("Failed to load {}s: {}", C::KIND, e));
+
+    Json(
+        all.into_iter()
+            .map(|item| View {
+                document_count: counts.get(&item.id()).copied().unwrap_or(0),
+                item,
+            })
+            .collect(),
+    )
+}
+
+pub async fn create<C: Classifier>(
+    State(state): State<Arc<AppState>>,
+    Json(mut new): Json<C::New>,
+) -> Result<(StatusCode, Json<C>), (StatusCode, String)> {
+    validate::<C>(&mut new, None)?;
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let item = C::insert(&mut conn, &new).map_err(name_conflict)?;
+
+    Ok((StatusCode::CREATED, Json(item)))
+}
+
+/// A new name is reindexed for all documents it is assigned to. A new pattern only applies to
+/// documents ingested from now on.
+pub async fn update<C: Classifier>(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(mut changes): Json<C::Changes>,
+) -> Result<Json<C>, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let current = C::find(&mut conn, id)
+        .unwrap_or_else(|e| panic!("Failed to load {}: {}", C::KIND, e))
+        .ok_or_else(not_found::<C>)?;
+
+    validate::<C>(&mut changes, Some(current.current_rule()))?;
+
+    if C::is_unchanged(&changes) {
+        return Ok(Json(current));
+    }
+
+    // it may be gone by now
+    let item = C::update(&mut conn, id, &changes)
+        .optional()
+        .map_err(name_conflict)?
+        .ok_or_else(not_found::<C>)?;
+
+    if changes.name().is_some() {
+        let document_ids = C::documents(&mut conn, id).expect("Failed to load documents");
+        reindex(&state, &mut conn, &document_ids).await;
+    }
+
+    Ok(Json(item))
+}
+
+/// Its documents are kept, without it.
+pub async fn delete<C: Classifier>(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+) -> StatusCode {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let document_ids = C::documents(&mut conn, id).expect("Failed to load documents");
+
+    let deleted =
+        C::delete(&mut conn, id).unwrap_or_else(|e| panic!("Failed to delete {}: {}", C::KIND, e));
+
+    if deleted == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+
+    reindex(&state, &mut conn, &document_ids).await;
+
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CorrespondentChanges;
+
+    fn changes(matching: Option<&str>, algorithm: Option<&str>) -> CorrespondentChanges {
+        CorrespondentChanges {
+            name: None,
+            matching: matching.map(str::to_string),
+            matching_algorithm: algorithm.map(str::to_string),
+            is_insensitive: None,
+        }
+    }
+
+    #[test]
+    fn checks_rules_as_they_will_be_once_changed() {
+        let mut new = changes(Some("acme"), Some(" Fuzzy"));
+        assert!(validate_rule(&mut new, None).is_ok());
+        assert_eq!(new.matching_algorithm.as_deref(), Some("fuzzy"));
+
+        // the current pattern is not a valid expression, so it cannot become one
+        let mut to_regex = changes(None, Some("regex"));
+        let current = Some(("(acme", "any"));
+        let error = validate_rule(&mut to_regex, current).unwrap_err();
+        assert_eq!(error.0, StatusCode::BAD_REQUEST);
+
+        // and a new pattern of the current algorithm is checked as such
+        let mut new_pattern = changes(Some("[a-"), None);
+        assert!(validate_rule(&mut new_pattern, Some(("\\d+", "regex"))).is_err());
+        assert!(new_pattern.matching_algorithm.is_none());
+
+        let mut unknown = changes(None, Some("sometimes"));
+        assert!(validate_rule(&mut unknown, None).is_err());
+    }
+
+    #[test]
+    fn needs_a_name() {
+        let mut unnamed = changes(None, None);
+        unnamed.name = Some("  ".to_string());
+
+        let error = validate::<crate::models::DocumentType>(&mut unnamed, None).unwrap_err();
+        assert_eq!(
+            error,
+            (
+                StatusCode::BAD_REQUEST,
+                "A document type needs a name".to_string()
+            )
+        );
+
+        unnamed.name = None;
+        assert!(validate::<crate::models::DocumentType>(&mut unnamed, None).is_ok());
+    }
+}
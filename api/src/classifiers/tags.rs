@@ -0,0 +1,234 @@
+// Tags are a tree, so besides the name and rule they share with correspondents and types,
+// they are checked for their parent and color, and named by their path in searches.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use diesel::{OptionalExtension, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper};
+use serde::Serialize;
+
+use super::{RuleFields, validate_rule};
+use crate::models::{NewTag, Tag, TagChanges};
+use crate::schema::{document_tags, tags};
+use crate::{AppState, reindex, tag_tree};
+
+impl RuleFields for NewTag {
+    fn name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+impl RuleFields for TagChanges {
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    fn rule(&mut self) -> (&mut Option<String>, Option<&str>) {
+        (&mut self.matching_algorithm, self.matching.as_deref())
+    }
+}
+
+#[derive(Serialize)]
+pub struct TagView {
+    #[serde(flatten)]
+    tag: Tag,
+    path: String,
+    document_count: i64,
+}
+
+pub async fn list(State(state): State<Arc<AppState>>) -> Json<Vec<TagView>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let all_tags = tag_tree::load_all(&mut conn).expect("Failed to load tags");
+
+    let counts: HashMap<i32, i64> = document_tags::table
+        .group_by(document_tags::tag_id)
+        .select((document_tags::tag_id, diesel::dsl::count_star()))
+        .load::<(i32, i64)>(&mut conn)
+        .expect("Failed to count tagged documents")
+        .into_iter()
+        .collect();
+
+    Json(
+        all_tags
+            .iter()
+            .map(|tag| TagView {
+                path: tag_tree::path(&all_tags, tag.id).join("/"),
+                document_count: counts.get(&tag.id).copied().unwrap_or(0),
+                tag: tag.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Check the name, color and parent of a new or changed tag. A tag cannot be moved below
+/// itself.
+fn validate_tag(
+    conn: &mut PgConnection,
+    id: Option<i32>,
+    name: Option<&str>,
+    color: Option<&str>,
+    parent_id: Option<i32>,
+) -> Result<(), (StatusCode, String)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+
+    if let Some(name) = name {
+        tag_tree::validate_name(name).map_err(bad_request)?;
+    }
+
+    if let Some(color) = color {
+        tag_tree::validate_color(color).map_err(bad_request)?;
+    }
+
+    if let Some(parent_id) = parent_id {
+        let all_tags = tag_tree::load_all(conn).expect("Failed to load tags");
+
+        if !all_tags.iter().any(|tag| tag.id == parent_id) {
+            return Err(bad_request(format!("There is no tag {}", parent_id)));
+        }
+
+        if let Some(id) = id
+            && tag_tree::descendants(&all_tags, id).contains(&parent_id)
+        {
+            return Err(bad_request(
+                "A tag cannot be nested below itself".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// A name is taken if a sibling has it already.
+fn tag_conflict(e: diesel::result::Error) -> (StatusCode, String) {
+    match e {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        ) => (
+            StatusCode::CONFLICT,
+            "There is a tag with this name already".to_string(),
+        ),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+pub async fn create(
+    State(state): State<Arc<AppState>>,
+    Json(mut tag): Json<NewTag>,
+) -> Result<(StatusCode, Json<Tag>), (StatusCode, String)> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    validate_tag(
+        &mut conn,
+        None,
+        Some(&tag.name),
+        tag.color.as_deref(),
+        tag.parent_id,
+    )?;
+    validate_rule(&mut tag, None)?;
+
+    let tag = diesel::insert_into(tags::table)
+        .values(&tag)
+        .returning(Tag::as_returning())
+        .get_result(&mut conn)
+        .map_err(tag_conflict)?;
+
+    Ok((StatusCode::CREATED, Json(tag)))
+}
+
+/// Renaming or moving a tag changes the path of every tag below it, so their documents are
+/// reindexed.
+pub async fn update(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<i32>,
+    Json(mut changes): Json<TagChanges>,
+) -> Result<Json<Tag>, (StatusCode, String)> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    validate_tag(
+        &mut conn,
+        Some(id),
+        changes.name.as_deref(),
+        changes.color.as_deref(),
+        changes.parent_id.flatten(),
+    )?;
+
+    let current: Option<(String, String)> = tags::table
+        .find(id)
+        .select((tags::matching, tags::matching_algorithm))
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load tag");
+
+    validate_rule(
+        &mut changes,
+        current
+            .as_ref()
+            .map(|(pattern, algorithm)| (pattern.as_str(), algorithm.as_str())),
+    )?;
+
+    let unchanged = changes.name.is_none()
+        && changes.color.is_none()
+        && changes.parent_id.is_none()
+        && changes.matching.is_none()
+        && changes.matching_algorithm.is_none()
+        && changes.is_insensitive.is_none();
+
+    // nothing to change makes an empty UPDATE, which diesel refuses
+    let tag = if unchanged {
+        tags::table
+            .find(id)
+            .select(Tag::as_select())
+            .first(&mut conn)
+            .optional()
+            .expect("Failed to load tag")
+    } else {
+        diesel::update(tags::table.find(id))
+            .set(&changes)
+            .returning(Tag::as_returning())
+            .get_result(&mut conn)
+            .optional()
+            .map_err(tag_conflict)?
+    }
+    .ok_or((StatusCode::NOT_FOUND, "No such tag".to_string()))?;
+
+    if changes.name.is_some() || changes.parent_id.is_some() {
+        let all_tags = tag_tree::load_all(&mut conn).expect("Failed to load tags");
+        let document_ids =
+            tag_tree::documents_with(&mut conn, &tag_tree::descendants(&all_tags, id))
+                .expect("Failed to load tagged documents");
+
+        reindex(&state, &mut conn, &document_ids).await;
+    }
+
+    Ok(Json(tag))
+}
+
+/// Deletes the tags below it too.
+pub async fn delete(State(state): State<Arc<AppState>>, Path(id): Path<i32>) -> StatusCode {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let all_tags = tag_tree::load_all(&mut conn).expect("Failed to load tags");
+    let document_ids = tag_tree::documents_with(&mut conn, &tag_tree::descendants(&all_tags, id))
+        .expect("Failed to load tagged documents");
+
+    let deleted = diesel::delete(tags::table.find(id))
+        .execute(&mut conn)
+        .expect("Failed to delete tag");
+
+    if deleted == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+
+    reindex(&state, &mut conn, &document_ids).await;
+
+    StatusCode::NO_CONTENT
+}
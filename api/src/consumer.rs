@@ -0,0 +1,335 @@
+// Hands-free ingestion: watch a "consume" directory (e.g. where a scanner drops its files) and
+// queue every new file once it has stopped changing.
+
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use notify::{Config, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+
+use crate::AppState;
+use crate::queue::{self, DuplicatePolicy, QueueError, UploadOptions};
+
+/// How often pending files are checked for having settled.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ConsumerConfig {
+    pub dir: PathBuf,
+    /// Where consumed files are moved to. They are deleted when this is not set.
+    pub done_dir: Option<PathBuf>,
+    /// Use the names of the subdirectories a file is in as its tags.
+    pub subdirs_as_tags: bool,
+    /// Poll instead of relying on inotify, which does not see changes made on network shares.
+    pub polling: bool,
+    pub poll_interval: Duration,
+    /// How long a file must stay the same size before it is considered completely written.
+    pub stable_delay: Duration,
+}
+
+impl ConsumerConfig {
+    /// Read the consumer settings, or `None` if no `CONSUME_DIR` is configured.
+    pub fn from_env() -> Option<Self> {
+        let dir = PathBuf::from(env::var("CONSUME_DIR").ok()?);
+
+        let flag = |name: &str| env::var(name).as_deref() == Ok("true");
+        let seconds = |name: &str, default: u64| {
+            Duration::from_secs(
+                env::var(name)
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+
+        Some(ConsumerConfig {
+            dir,
+            done_dir: env::var("CONSUME_DONE_DIR").ok().map(PathBuf::from),
+            subdirs_as_tags: flag("CONSUME_SUBDIRS_AS_TAGS"),
+            polling: flag("CONSUME_POLLING"),
+            poll_interval: seconds("CONSUME_POLL_INTERVAL", 10),
+            stable_delay: seconds("CONSUME_STABLE_SECS", 5),
+        })
+    }
+}
+
+/// A file in the consume directory that has not been queued yet.
+struct Candidate {
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+    unchanged_since: Instant,
+}
+
+impl Candidate {
+    fn new() -> Self {
+        Candidate {
+            size: None,
+            modified: None,
+            unchanged_since: Instant::now(),
+        }
+    }
+}
+
+pub async fn run(state: Arc<AppState>, mut config: ConsumerConfig) {
+    if let Err(e) = std::fs::create_dir_all(&config.dir) {
+        println!(
+            "Failed to create consume directory {}: {}",
+            config.dir.display(),
+            e
+        );
+        return;
+    }
+
+    // so that the paths we get from the watcher and from scanning always line up
+    config.dir = std::fs::canonicalize(&config.dir).unwrap_or(config.dir);
+
+    if let Some(done_dir) = &config.done_dir {
+        if let Err(e) = std::fs::create_dir_all(done_dir) {
+            println!("Failed to create {}: {}", done_dir.display(), e);
+            return;
+        }
+        config.done_dir = std::fs::canonicalize(done_dir).ok();
+    }
+
+    let (tx, mut rx) = unbounded_channel();
+
+    // dropping the watcher stops it, so it has to live as long as this loop
+    let _watcher = match watch(&config, tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            println!("Failed to watch {}: {}", config.dir.display(), e);
+            return;
+        }
+    };
+
+    println!("Consuming files from {}", config.dir.display());
+
+    let mut pending: HashMap<PathBuf, Candidate> = HashMap::new();
+
+    // files that were dropped in while the server was down
+    let mut existing = Vec::new();
+    scan(&config.dir, &mut existing);
+    for path in existing {
+        if is_candidate(&config, &path) {
+            pending.insert(path, Candidate::new());
+        }
+    }
+
+    let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            Some(path) = rx.recv() => {
+                if is_candidate(&config, &path) {
+                    pending.entry(path).or_insert_with(Candidate::new);
+                }
+            }
+            _ = ticker.tick() => {
+                for path in settled(&config, &mut pending) {
+                    consume(&state, &config, &path).await;
+                }
+            }
+        }
+    }
+}
+
+/// Watch the consume directory with inotify (or the platform equivalent), falling back to
+/// polling if that is unavailable or polling was asked for.
+fn watch(
+    config: &ConsumerConfig,
+    tx: UnboundedSender<PathBuf>,
+) -> notify::Result<Box<dyn Watcher + Send>> {
+    let mut watcher: Box<dyn Watcher + Send> = if config.polling {
+        Box::new(poll_watcher(config, tx)?)
+    } else {
+        match RecommendedWatcher::new(forward(tx.clone()), Config::default()) {
+            Ok(watcher) => Box::new(watcher),
+            Err(e) => {
+                println!("Falling back to polling the consume directory: {}", e);
+                Box::new(poll_watcher(config, tx)?)
+            }
+        }
+    };
+
+    watcher.watch(&config.dir, RecursiveMode::Recursive)?;
+
+    Ok(watcher)
+}
+
+fn poll_watcher(
+    config: &ConsumerConfig,
+    tx: UnboundedSender<PathBuf>,
+) -> notify::Result<PollWatcher> {
+    PollWatcher::new(
+        forward(tx),
+        Config::default().with_poll_interval(config.poll_interval),
+    )
+}
+
+/// Forward the paths of created and modified files to the consumer loop.
+fn forward(
+    tx: UnboundedSender<PathBuf>,
+) -> impl FnMut(notify::Result<notify::Event>) + Send + 'static {
+    move |result| match result {
+        Ok(event) => {
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+        }
+        Err(e) => println!("Error watching the consume directory: {}", e),
+    }
+}
+
+fn scan(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            scan(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// Skip hidden and temporary files (which scanners and SMB clients write while copying) and
+/// anything already moved to the done directory.
+fn is_candidate(config: &ConsumerConfig, path: &Path) -> bool {
+    if config
+        .done_dir
+        .as_ref()
+        .is_some_and(|done_dir| path.starts_with(done_dir))
+    {
+        return false;
+    }
+
+    let hidden = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .is_none_or(|name| name.starts_with('.') || name.starts_with('~'));
+
+    !hidden && path.starts_with(&config.dir)
+}
+
+/// Update the pending files and take out the ones that have not changed for long enough.
+fn settled(config: &ConsumerConfig, pending: &mut HashMap<PathBuf, Candidate>) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+
+    pending.retain(|path, candidate| {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false; // gone again
+        };
+
+        if !metadata.is_file() {
+            return false;
+        }
+
+        let size = Some(metadata.len());
+        let modified = metadata.modified().ok();
+
+        if candidate.size != size || candidate.modified != modified {
+            candidate.size = size;
+            candidate.modified = modified;
+            candidate.unchanged_since = Instant::now();
+            return true;
+        }
+
+        if candidate.unchanged_since.elapsed() < config.stable_delay {
+            return true;
+        }
+
+        ready.push(path.clone());
+        false
+    });
+
+    ready
+}
+
+async fn consume(state: &AppState, config: &ConsumerConfig, path: &Path) {
+    let tags = if config.subdirs_as_tags {
+        subdir_tags(&config.dir, path)
+    } else {
+        Vec::new()
+    };
+
+    let upload = match queue::stage_file(path, tags).await {
+        Ok(upload) => upload,
+        Err(e) => {
+            println!("Failed to stage {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    let options = UploadOptions {
+        ocr_config: state.ocr_config.clone(),
+        on_duplicate: DuplicatePolicy::Reject,
+        parent_id: None,
+        password: None,
+    };
+
+    match queue::enqueue(state, vec![upload], &options) {
+        Ok(queued) => {
+            for task in queued {
+                println!("Queued {} as task {}", path.display(), task.id);
+            }
+        }
+        Err(QueueError::Duplicate { document_id, .. }) => {
+            println!(
+                "{} is a duplicate of {}, not ingesting it again",
+                path.display(),
+                document_id
+            );
+        }
+        Err(QueueError::Internal(e)) => {
+            // left in place, it is picked up again once it changes or the server restarts
+            println!("Failed to queue {}: {}", path.display(), e);
+            return;
+        }
+    }
+
+    if let Err(e) = clean_up(config, path).await {
+        println!("Failed to clean up {}: {}", path.display(), e);
+    }
+}
+
+/// The names of the directories between the consume directory and the file, outermost first.
+fn subdir_tags(root: &Path, path: &Path) -> Vec<String> {
+    path.parent()
+        .and_then(|parent| parent.strip_prefix(root).ok())
+        .map(|relative| {
+            relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Move a consumed file to the done directory, keeping its subdirectories, or delete it.
+async fn clean_up(config: &ConsumerConfig, path: &Path) -> std::io::Result<()> {
+    let Some(done_dir) = &config.done_dir else {
+        return tokio::fs::remove_file(path).await;
+    };
+
+    let relative = path.strip_prefix(&config.dir).unwrap_or(path);
+    let target = done_dir.join(relative);
+
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    // renaming fails across file systems, e.g. from a network share to a local disk
+    if tokio::fs::rename(path, &target).await.is_err() {
+        tokio::fs::copy(path, &target).await?;
+        tokio::fs::remove_file(path).await?;
+    }
+
+    Ok(())
+}
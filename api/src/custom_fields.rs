@@ -0,0 +1,617 @@
+// Fields defined by the user, e.g. the amount and due date of invoices, with a value per
+// document. Values are indexed by the name of their field in the `custom` JSON field, and
+// searches can name the field alone, e.g. `amount:>100` for `custom.amount:>100`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use chrono::{NaiveDate, NaiveTime};
+use diesel::{
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper,
+};
+use regex::{Captures, Regex};
+use serde_json::Value;
+use tantivy::schema::OwnedValue;
+
+use crate::models::{CustomField, CustomFieldValue, FieldValue};
+use crate::schema::{custom_field_values, custom_fields};
+
+/// The fields of the index, which a custom field would hide in searches.
+const RESERVED_NAMES: [&str; 14] = [
+    "id",
+    "title",
+    "body",
+    "from",
+    "to",
+    "subject",
+    "date",
+    "page",
+    "page_body",
+    "created",
+    "tags",
+    "correspondent",
+    "type",
+    "custom",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    String,
+    Integer,
+    /// An amount of money, optionally with an ISO 4217 currency, e.g. `EUR12.50`.
+    Monetary,
+    Date,
+    Boolean,
+    Url,
+    /// One of the `select_options` of the field.
+    Select,
+    /// The id of another document.
+    DocumentLink,
+}
+
+impl FromStr for DataType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "string" => Ok(DataType::String),
+            "integer" => Ok(DataType::Integer),
+            "monetary" => Ok(DataType::Monetary),
+            "date" => Ok(DataType::Date),
+            "boolean" => Ok(DataType::Boolean),
+            "url" => Ok(DataType::Url),
+            "select" => Ok(DataType::Select),
+            "document_link" => Ok(DataType::DocumentLink),
+            other => Err(format!(
+                "Unknown data type `{}`, expected one of string, integer, monetary, date, \
+                 boolean, url, select, document_link",
+                other
+            )),
+        }
+    }
+}
+
+impl DataType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataType::String => "string",
+            DataType::Integer => "integer",
+            DataType::Monetary => "monetary",
+            DataType::Date => "date",
+            DataType::Boolean => "boolean",
+            DataType::Url => "url",
+            DataType::Select => "select",
+            DataType::DocumentLink => "document_link",
+        }
+    }
+
+    /// What a value has to look like, for error messages.
+    fn expectation(&self) -> &'static str {
+        match self {
+            DataType::String => "a string",
+            DataType::Integer => "an integer",
+            DataType::Monetary => "an amount like 12.50 or EUR12.50",
+            DataType::Date => "a date like 2025-08-13",
+            DataType::Boolean => "true or false",
+            DataType::Url => "an http or https URL",
+            DataType::Select => "one of the options of the field",
+            DataType::DocumentLink => "the id of another document",
+        }
+    }
+}
+
+/// The data type of a field as stored, which was validated when the field was created.
+fn data_type(field: &CustomField) -> DataType {
+    field.data_type.parse().unwrap_or(DataType::String)
+}
+
+/// Names are used as field names in searches, so only letters, digits and `_` are allowed.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid {
+        return Err(format!(
+            "Custom field names can only contain letters, digits and `_`, starting with a \
+             letter: `{}`",
+            name
+        ));
+    }
+
+    if RESERVED_NAMES.contains(&name.to_lowercase().as_str()) {
+        return Err(format!("`{}` is already a search field", name));
+    }
+
+    Ok(())
+}
+
+/// Where the values of a field are in the `custom` field of the index.
+pub fn key(field: &CustomField) -> String {
+    field.name.to_lowercase()
+}
+
+static AMOUNT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([A-Z]{3})?\s*(-)?(\d+)(?:[.,](\d{1,2}))?$").unwrap());
+
+/// `12.50`, `EUR12.50` or `EUR 12,5` as cents and the currency.
+fn parse_amount(text: &str) -> Option<(i64, Option<String>)> {
+    let captures = AMOUNT.captures(text.trim())?;
+
+    let units: i64 = captures.get(3)?.as_str().parse().ok()?;
+    let cents: i64 = match captures.get(4).map(|cents| cents.as_str()) {
+        Some(cents) if cents.len() == 1 => cents.parse::<i64>().ok()? * 10,
+        Some(cents) => cents.parse().ok()?,
+        None => 0,
+    };
+
+    let amount = units.checked_mul(100)?.checked_add(cents)?;
+    let sign = if captures.get(2).is_some() { -1 } else { 1 };
+
+    Some((
+        sign * amount,
+        captures.get(1).map(|c| c.as_str().to_string()),
+    ))
+}
+
+fn format_amount(cents: i64, currency: Option<&str>) -> String {
+    format!(
+        "{}{}{}.{:02}",
+        currency.unwrap_or(""),
+        if cents < 0 { "-" } else { "" },
+        cents.abs() / 100,
+        cents.abs() % 100
+    )
+}
+
+/// The row to store for a value the API got, or `None` for `null`, which removes the value.
+/// Whether a linked document exists is left to the caller.
+pub fn parse_value(
+    field: &CustomField,
+    document_id: &str,
+    value: &Value,
+) -> Result<Option<CustomFieldValue>, String> {
+    if value.is_null() {
+        return Ok(None);
+    }
+
+    let data_type = data_type(field);
+    let invalid = || {
+        format!(
+            "Invalid value for `{}`: expected {}",
+            field.name,
+            data_type.expectation()
+        )
+    };
+
+    let mut row = CustomFieldValue {
+        document_id: document_id.to_string(),
+        field_id: field.id,
+        value_text: None,
+        value_integer: None,
+        value_monetary: None,
+        value_date: None,
+        value_boolean: None,
+        value_document_id: None,
+    };
+
+    match data_type {
+        DataType::String => row.value_text = Some(value.as_str().ok_or_else(invalid)?.to_string()),
+        DataType::Url => {
+            let url = value.as_str().ok_or_else(invalid)?.trim();
+
+            let valid = (url.starts_with("http://") || url.starts_with("https://"))
+                && !url.contains(char::is_whitespace);
+
+            if !valid {
+                return Err(invalid());
+            }
+
+            row.value_text = Some(url.to_string());
+        }
+        DataType::Select => {
+            let option = value.as_str().ok_or_else(invalid)?;
+
+            if !field.select_options.iter().any(|o| o == option) {
+                return Err(invalid());
+            }
+
+            row.value_text = Some(option.to_string());
+        }
+        DataType::Integer => row.value_integer = Some(value.as_i64().ok_or_else(invalid)?),
+        DataType::Monetary => {
+            let (cents, currency) = match value {
+                Value::Number(number) => {
+                    let amount = number.as_f64().ok_or_else(invalid)?;
+                    ((amount * 100.0).round() as i64, None)
+                }
+                Value::String(text) => parse_amount(text).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            };
+
+            row.value_monetary = Some(cents);
+            row.value_text = currency;
+        }
+        DataType::Date => {
+            let date = value.as_str().ok_or_else(invalid)?;
+            row.value_date =
+                Some(NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").map_err(|_| invalid())?);
+        }
+        DataType::Boolean => row.value_boolean = Some(value.as_bool().ok_or_else(invalid)?),
+        DataType::DocumentLink => {
+            let linked = value.as_str().ok_or_else(invalid)?;
+
+            if linked == document_id {
+                return Err(format!("`{}` cannot link a document to itself", field.name));
+            }
+
+            row.value_document_id = Some(linked.to_string());
+        }
+    }
+
+    Ok(Some(row))
+}
+
+/// A stored value as the API returns it.
+fn to_json(field: &CustomField, row: &CustomFieldValue) -> Value {
+    match data_type(field) {
+        DataType::String | DataType::Url | DataType::Select => row.value_text.clone().into(),
+        DataType::Integer => row.value_integer.into(),
+        DataType::Monetary => row
+            .value_monetary
+            .map(|cents| format_amount(cents, row.value_text.as_deref()))
+            .into(),
+        DataType::Date => row.value_date.map(|date| date.to_string()).into(),
+        DataType::Boolean => row.value_boolean.into(),
+        DataType::DocumentLink => row.value_document_id.clone().into(),
+    }
+}
+
+/// A stored value as it is indexed, with amounts of money in units so `amount:>100` means
+/// more than 100 euros (or dollars). The currency is not indexed, so searches compare amounts
+/// in different currencies as if they were the same.
+fn to_index(field: &CustomField, row: &CustomFieldValue) -> Option<OwnedValue> {
+    match data_type(field) {
+        DataType::String | DataType::Url | DataType::Select => {
+            row.value_text.clone().map(OwnedValue::Str)
+        }
+        DataType::Integer => row.value_integer.map(OwnedValue::I64),
+        DataType::Monetary => row
+            .value_monetary
+            .map(|cents| OwnedValue::F64(cents as f64 / 100.0)),
+        DataType::Date => row.value_date.map(|date| {
+            OwnedValue::Date(tantivy::DateTime::from_timestamp_secs(
+                date.and_time(NaiveTime::MIN).and_utc().timestamp(),
+            ))
+        }),
+        DataType::Boolean => row.value_boolean.map(OwnedValue::Bool),
+        DataType::DocumentLink => row.value_document_id.clone().map(OwnedValue::Str),
+    }
+}
+
+pub fn load_all(conn: &mut PgConnection) -> QueryResult<Vec<CustomField>> {
+    custom_fields::table
+        .select(CustomField::as_select())
+        .order(custom_fields::name)
+        .load(conn)
+}
+
+/// Replace the custom field values of a document.
+pub fn assign(
+    conn: &mut PgConnection,
+    document_id: &str,
+    rows: &[CustomFieldValue],
+) -> QueryResult<()> {
+    diesel::delete(
+        custom_field_values::table.filter(custom_field_values::document_id.eq(document_id)),
+    )
+    .execute(conn)?;
+
+    diesel::insert_into(custom_field_values::table)
+        .values(rows)
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// The custom field values of each of the documents.
+pub fn by_document(
+    conn: &mut PgConnection,
+    document_ids: &[String],
+) -> QueryResult<HashMap<String, Vec<FieldValue>>> {
+    let fields = load_all(conn)?;
+
+    let rows: Vec<CustomFieldValue> = custom_field_values::table
+        .filter(custom_field_values::document_id.eq_any(document_ids))
+        .select(CustomFieldValue::as_select())
+        .load(conn)?;
+
+    let mut by_document: HashMap<String, Vec<FieldValue>> = HashMap::new();
+
+    for row in rows {
+        if let Some(field) = fields.iter().find(|field| field.id == row.field_id) {
+            by_document
+                .entry(row.document_id.clone())
+                .or_default()
+                .push(FieldValue {
+                    field: field.id,
+                    value: to_json(field, &row),
+                });
+        }
+    }
+
+    Ok(by_document)
+}
+
+/// The values of a document as they go into the `custom` field of the index.
+pub fn index_values(
+    conn: &mut PgConnection,
+    document_id: &str,
+) -> QueryResult<BTreeMap<String, OwnedValue>> {
+    let rows: Vec<(CustomFieldValue, CustomField)> = custom_field_values::table
+        .inner_join(custom_fields::table)
+        .filter(custom_field_values::document_id.eq(document_id))
+        .select((CustomFieldValue::as_select(), CustomField::as_select()))
+        .load(conn)?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|(row, field)| Some((key(field), to_index(field, row)?)))
+        .collect())
+}
+
+/// Ids of the documents that have a value for the field.
+pub fn documents_with(conn: &mut PgConnection, field_id: i32) -> QueryResult<Vec<String>> {
+    custom_field_values::table
+        .filter(custom_field_values::field_id.eq(field_id))
+        .select(custom_field_values::document_id)
+        .load(conn)
+}
+
+/// `name:value` in a search, with the value being a range, a comparison, a phrase or a word,
+/// or a quoted phrase of its own, which is matched so what looks like a field in it is not.
+static FIELD_CLAUSE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#""[^"]*"|(^|[\s(+-])([A-Za-z][A-Za-z0-9_]*):([\[{][^\]}]*[\]}]|[<>]=?[^\s)]+|"[^"]*"|[^\s)]+)"#,
+    )
+    .unwrap()
+});
+
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"-?\d+(\.\d+)?").unwrap());
+
+static DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\d{4}-\d{2}-\d{2}(T[0-9:.]+(?:Z|[+-]\d{2}:\d{2}))?").unwrap());
+
+/// Point the custom fields named in a search at the `custom` field of the index, e.g.
+/// `amount:>100` to `custom.amount:>100.0`. Amounts are indexed as decimals and dates as
+/// timestamps, so values are written as those. Phrases in quotes are left as they are.
+pub fn rewrite_query(query: &str, fields: &[CustomField]) -> String {
+    FIELD_CLAUSE
+        .replace_all(query, |captures: &Captures| {
+            let Some(name) = captures.get(2).map(|name| name.as_str()) else {
+                return captures[0].to_string();
+            };
+
+            let Some(field) = fields
+                .iter()
+                .find(|field| field.name.eq_ignore_ascii_case(name))
+            else {
+                return captures[0].to_string();
+            };
+
+            let value = match data_type(field) {
+                DataType::Monetary => NUMBER
+                    .replace_all(&captures[3], |number: &Captures| match number.get(1) {
+                        Some(_) => number[0].to_string(),
+                        None => format!("{}.0", &number[0]),
+                    })
+                    .into_owned(),
+                DataType::Date => {
+                    let value =
+                        DATE.replace_all(&captures[3], |date: &Captures| match date.get(1) {
+                            Some(_) => date[0].to_string(),
+                            None => format!("{}T00:00:00Z", &date[0]),
+                        });
+
+                    // the colons of a timestamp only parse in a range or quoted
+                    if value.starts_with(|c: char| c.is_ascii_digit()) {
+                        format!("\"{}\"", value)
+                    } else {
+                        value.into_owned()
+                    }
+                }
+                _ => captures[3].to_string(),
+            };
+
+            format!("{}custom.{}:{}", &captures[1], key(field), value)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tantivy::query::QueryParser;
+    use tantivy::schema::{FAST, STORED, Schema, TEXT};
+    use tantivy::{Index, TantivyDocument};
+
+    use super::*;
+
+    fn field(id: i32, name: &str, data_type: &str) -> CustomField {
+        CustomField {
+            id,
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            select_options: Vec::new(),
+        }
+    }
+
+    fn fields() -> Vec<CustomField> {
+        vec![
+            field(1, "Amount", "monetary"),
+            field(2, "due", "date"),
+            field(3, "reference", "string"),
+            field(4, "pages", "integer"),
+        ]
+    }
+
+    #[test]
+    fn parses_amounts_as_cents() {
+        let cases = [
+            ("12.50", Some((1250, None))),
+            ("12,5", Some((1250, None))),
+            ("12", Some((1200, None))),
+            ("-3.07", Some((-307, None))),
+            ("EUR12.50", Some((1250, Some("EUR")))),
+            (" USD 0,99 ", Some((99, Some("USD")))),
+            ("12.505", None),
+            ("eur12", None),
+            ("12 EUR", None),
+            ("", None),
+        ];
+
+        for (text, expected) in cases {
+            let expected = expected.map(|(cents, currency)| (cents, currency.map(str::to_string)));
+            assert_eq!(parse_amount(text), expected, "{:?}", text);
+        }
+
+        assert_eq!(format_amount(1250, Some("EUR")), "EUR12.50");
+        assert_eq!(format_amount(-307, None), "-3.07");
+    }
+
+    #[test]
+    fn points_custom_fields_at_the_custom_field_of_the_index() {
+        let cases = [
+            ("amount:>100", "custom.amount:>100.0"),
+            ("amount:>=99.5", "custom.amount:>=99.5"),
+            ("AMOUNT:[10 TO 20]", "custom.amount:[10.0 TO 20.0]"),
+            ("amount:{-5 TO 20.25}", "custom.amount:{-5.0 TO 20.25}"),
+            // amounts are indexed as decimals, so whole ones get a decimal point
+            ("amount:120", "custom.amount:120.0"),
+            ("amount:<5", "custom.amount:<5.0"),
+            ("amount:-3", "custom.amount:-3.0"),
+            ("amount:[10 TO 20.5]", "custom.amount:[10.0 TO 20.5]"),
+            // and integers as they are
+            ("pages:>10", "custom.pages:>10"),
+            ("pages:<=3", "custom.pages:<=3"),
+            ("pages:12", "custom.pages:12"),
+            ("PAGES:[1 TO 5]", "custom.pages:[1 TO 5]"),
+            (
+                "due:[2025-09-01 TO 2025-09-30]",
+                "custom.due:[2025-09-01T00:00:00Z TO 2025-09-30T00:00:00Z]",
+            ),
+            ("due:>2025-09-01", "custom.due:>2025-09-01T00:00:00Z"),
+            ("due:2025-09-01", r#"custom.due:"2025-09-01T00:00:00Z""#),
+            (
+                r#"reference:"INV 42" invoice"#,
+                r#"custom.reference:"INV 42" invoice"#,
+            ),
+            (
+                "invoice AND (amount:>100 OR -due:<2025-01-01)",
+                "invoice AND (custom.amount:>100.0 OR -custom.due:<2025-01-01T00:00:00Z)",
+            ),
+            // fields that are not custom ones are left alone
+            ("title:invoice unknown:5", "title:invoice unknown:5"),
+            // and so is what is in a phrase
+            (r#""amount: 5 due:today""#, r#""amount: 5 due:today""#),
+            (
+                r#""see reference:42" amount:5"#,
+                r#""see reference:42" custom.amount:5.0"#,
+            ),
+            ("email@amount:5", "email@amount:5"),
+        ];
+
+        for (query, expected) in cases {
+            assert_eq!(rewrite_query(query, &fields()), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn rewritten_queries_find_documents_by_their_values() {
+        let mut schema_builder = Schema::builder();
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let custom = schema_builder.add_json_field("custom", TEXT | FAST);
+        let schema = schema_builder.build();
+
+        let index = Index::create_in_ram(schema);
+        let mut writer = index.writer(15_000_000).unwrap();
+
+        for (name, cents, due, page_count) in [
+            ("cheap", 1050, (2025, 9, 5), 2),
+            ("pricey", 12000, (2025, 10, 1), 14),
+        ] {
+            let (amount, due_date) = (field(1, "amount", "monetary"), field(2, "due", "date"));
+            let pages = field(4, "pages", "integer");
+            let row = CustomFieldValue {
+                document_id: name.to_string(),
+                field_id: 1,
+                value_text: Some("EUR".to_string()),
+                value_integer: None,
+                value_monetary: Some(cents),
+                value_date: None,
+                value_boolean: None,
+                value_document_id: None,
+            };
+            let due_row = CustomFieldValue {
+                value_text: None,
+                value_monetary: None,
+                value_date: NaiveDate::from_ymd_opt(due.0, due.1, due.2),
+                ..row.clone()
+            };
+            let pages_row = CustomFieldValue {
+                value_text: None,
+                value_monetary: None,
+                value_integer: Some(page_count),
+                ..row.clone()
+            };
+
+            let values = BTreeMap::from([
+                (key(&amount), to_index(&amount, &row).unwrap()),
+                (key(&due_date), to_index(&due_date, &due_row).unwrap()),
+                (key(&pages), to_index(&pages, &pages_row).unwrap()),
+            ]);
+
+            let mut entry = TantivyDocument::new();
+            entry.add_text(title, name);
+            entry.add_object(custom, values);
+            writer.add_document(entry).unwrap();
+        }
+
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let parser = QueryParser::for_index(&index, vec![title]);
+
+        let search = |query: &str| {
+            let query = parser
+                .parse_query(&rewrite_query(query, &fields()))
+                .unwrap();
+            let mut titles: Vec<String> = searcher
+                .search(&query, &tantivy::collector::TopDocs::with_limit(10))
+                .unwrap()
+                .into_iter()
+                .map(|(_, address)| {
+                    let doc: TantivyDocument = searcher.doc(address).unwrap();
+                    doc.get_first(title)
+                        .and_then(|value| tantivy::schema::Value::as_str(&value))
+                        .unwrap()
+                        .to_string()
+                })
+                .collect();
+            titles.sort();
+            titles
+        };
+
+        assert_eq!(search("amount:>100"), vec!["pricey"]);
+        assert_eq!(search("amount:[10 TO 20]"), vec!["cheap"]);
+        assert_eq!(search("amount:10.5"), vec!["cheap"]);
+        assert_eq!(search("amount:120"), vec!["pricey"]);
+        assert_eq!(search("amount:<100"), vec!["cheap"]);
+        assert_eq!(search("pages:>10"), vec!["pricey"]);
+        assert_eq!(search("pages:<10"), vec!["cheap"]);
+        assert_eq!(search("pages:2"), vec!["cheap"]);
+        assert_eq!(search("pages:[1 TO 14]"), vec!["cheap", "pricey"]);
+        assert_eq!(search("due:[2025-09-01 TO 2025-09-30]"), vec!["cheap"]);
+        assert_eq!(search("due:2025-10-01"), vec!["pricey"]);
+        assert_eq!(search("due:>2025-01-01"), vec!["cheap", "pricey"]);
+    }
+}
@@ -0,0 +1,343 @@
+// Finding the date a document was written in its text, e.g. the date line of a letter
+
+use std::env;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use chrono::{Datelike, NaiveDate};
+use regex::{Captures, Regex};
+
+/// Month names and abbreviations in English, German and French, lowercase.
+const MONTHS: [(&str, u32); 55] = [
+    ("january", 1),
+    ("januar", 1),
+    ("jänner", 1),
+    ("janvier", 1),
+    ("janv", 1),
+    ("jan", 1),
+    ("february", 2),
+    ("februar", 2),
+    ("février", 2),
+    ("fevrier", 2),
+    ("févr", 2),
+    ("feb", 2),
+    ("march", 3),
+    ("märz", 3),
+    ("mars", 3),
+    ("mar", 3),
+    ("mär", 3),
+    ("april", 4),
+    ("avril", 4),
+    ("apr", 4),
+    ("avr", 4),
+    ("may", 5),
+    ("mai", 5),
+    ("june", 6),
+    ("juni", 6),
+    ("juin", 6),
+    ("jun", 6),
+    ("july", 7),
+    ("juli", 7),
+    ("juillet", 7),
+    ("juil", 7),
+    ("jul", 7),
+    ("august", 8),
+    ("août", 8),
+    ("aout", 8),
+    ("aug", 8),
+    ("september", 9),
+    ("septembre", 9),
+    ("sept", 9),
+    ("sep", 9),
+    ("october", 10),
+    ("oktober", 10),
+    ("octobre", 10),
+    ("oct", 10),
+    ("okt", 10),
+    ("november", 11),
+    ("novembre", 11),
+    ("nov", 11),
+    ("december", 12),
+    ("dezember", 12),
+    ("décembre", 12),
+    ("decembre", 12),
+    ("déc", 12),
+    ("dez", 12),
+    ("dec", 12),
+];
+
+/// Which comes first in numeric dates like `03/04/2025`, from `DATE_ORDER`. Dates written with
+/// dots (`13.08.2025`) are always read day first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DateOrder {
+    /// Day, month, year as in most of Europe.
+    #[default]
+    Dmy,
+    /// Month, day, year as in the US.
+    Mdy,
+}
+
+impl FromStr for DateOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "dmy" => Ok(DateOrder::Dmy),
+            "mdy" => Ok(DateOrder::Mdy),
+            other => Err(format!(
+                "Unknown date order `{}`, expected one of dmy, mdy",
+                other
+            )),
+        }
+    }
+}
+
+impl DateOrder {
+    pub fn from_env() -> Self {
+        match env::var("DATE_ORDER").map(|order| order.parse()) {
+            Ok(Ok(order)) => order,
+            Ok(Err(e)) => {
+                println!("Ignoring DATE_ORDER: {}", e);
+                DateOrder::default()
+            }
+            Err(_) => DateOrder::default(),
+        }
+    }
+}
+
+static ISO_DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(\d{4})[-/](\d{1,2})[-/](\d{1,2})\b").unwrap());
+
+static NUMERIC_DATE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(\d{1,2})([./-])(\d{1,2})([./-])(\d{4}|\d{2})\b").unwrap());
+
+/// `13 August 2025`, `13. August 2025`, `1er janvier 2025`
+static DAY_MONTH_NAME: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)\b(\d{{1,2}})(?:\.|st|nd|rd|th|er)?\s+({})\.?,?\s+(\d{{4}})\b",
+        month_pattern()
+    ))
+    .unwrap()
+});
+
+/// `August 13, 2025`, `Aug 13th 2025`
+static MONTH_NAME_DAY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)\b({})\.?\s+(\d{{1,2}})(?:st|nd|rd|th)?,?\s+(\d{{4}})\b",
+        month_pattern()
+    ))
+    .unwrap()
+});
+
+/// All month names as a regex alternation, longest first so `juni` is not read as `jun`.
+fn month_pattern() -> String {
+    let mut names: Vec<&str> = MONTHS.iter().map(|(name, _)| *name).collect();
+
+    names.sort_by_key(|name| std::cmp::Reverse(name.chars().count()));
+
+    names.join("|")
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    let name = name.to_lowercase();
+
+    MONTHS
+        .iter()
+        .find(|(month, _)| *month == name)
+        .map(|(_, number)| *number)
+}
+
+/// Two-digit years are read as 1970 to 2069.
+fn full_year(year: &str) -> Option<i32> {
+    let number: i32 = year.parse().ok()?;
+
+    Some(match year.len() {
+        2 if number < 70 => 2000 + number,
+        2 => 1900 + number,
+        _ => number,
+    })
+}
+
+fn number(captures: &Captures, group: usize) -> Option<u32> {
+    captures.get(group)?.as_str().parse().ok()
+}
+
+/// `2025-08-13`, `2025/08/13`
+fn iso_date(captures: &Captures, _order: DateOrder) -> Option<NaiveDate> {
+    let year = full_year(captures.get(1)?.as_str())?;
+    NaiveDate::from_ymd_opt(year, number(captures, 2)?, number(captures, 3)?)
+}
+
+/// `13.08.2025`, `13/08/25`, `08-13-2025`
+fn numeric_date(captures: &Captures, order: DateOrder) -> Option<NaiveDate> {
+    let separator = captures.get(2)?.as_str();
+
+    // `13.08-2025` is not a date
+    if separator != captures.get(4)?.as_str() {
+        return None;
+    }
+
+    let year = captures.get(5)?.as_str();
+
+    // `1.2.24` is more likely a version than a date, short years only come with `01.02.24`
+    if year.len() == 2 && (captures.get(1)?.len() < 2 || captures.get(3)?.len() < 2) {
+        return None;
+    }
+
+    let year = full_year(year)?;
+    let (first, second) = (number(captures, 1)?, number(captures, 3)?);
+
+    let (day, month) = match (separator, order) {
+        (".", _) | (_, DateOrder::Dmy) => (first, second),
+        (_, DateOrder::Mdy) => (second, first),
+    };
+
+    // `08/13/2025` can only be month first, whatever the configured order
+    NaiveDate::from_ymd_opt(year, month, day).or_else(|| NaiveDate::from_ymd_opt(year, day, month))
+}
+
+fn day_month_name(captures: &Captures, _order: DateOrder) -> Option<NaiveDate> {
+    let month = month_number(captures.get(2)?.as_str())?;
+    let year = full_year(captures.get(3)?.as_str())?;
+    NaiveDate::from_ymd_opt(year, month, number(captures, 1)?)
+}
+
+fn month_name_day(captures: &Captures, _order: DateOrder) -> Option<NaiveDate> {
+    let month = month_number(captures.get(1)?.as_str())?;
+    let year = full_year(captures.get(3)?.as_str())?;
+    NaiveDate::from_ymd_opt(year, month, number(captures, 2)?)
+}
+
+/// Whether a match is part of a longer run of dotted numbers, like the version `1.12.2024.3`.
+fn in_dotted_number(text: &str, start: usize, end: usize) -> bool {
+    let mut after = text[end..].chars();
+    let mut before = text[..start].chars().rev();
+
+    (after.next() == Some('.') && after.next().is_some_and(|c| c.is_ascii_digit()))
+        || (before.next() == Some('.') && before.next().is_some_and(|c| c.is_ascii_digit()))
+}
+
+/// The most likely date of a document: the first date in its text that is not in the future.
+/// Later dates tend to be due dates, references to earlier letters and the like.
+pub fn find_date(text: &str, order: DateOrder, today: NaiveDate) -> Option<NaiveDate> {
+    type Parse = fn(&Captures, DateOrder) -> Option<NaiveDate>;
+
+    let patterns: [(&Regex, Parse); 4] = [
+        (&ISO_DATE, iso_date),
+        (&NUMERIC_DATE, numeric_date),
+        (&DAY_MONTH_NAME, day_month_name),
+        (&MONTH_NAME_DAY, month_name_day),
+    ];
+
+    patterns
+        .iter()
+        .flat_map(|(regex, parse)| {
+            regex.captures_iter(text).filter_map(move |captures| {
+                let found = captures.get(0)?;
+
+                if in_dotted_number(text, found.start(), found.end()) {
+                    return None;
+                }
+
+                Some((found.start(), parse(&captures, order)?))
+            })
+        })
+        .filter(|(_, date)| date.year() >= 1900 && *date <= today)
+        .min_by_key(|(position, _)| *position)
+        .map(|(_, date)| date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(year, month, day)
+    }
+
+    #[test]
+    fn finds_dates_as_they_are_written() {
+        let today = NaiveDate::from_ymd_opt(2025, 10, 18).unwrap();
+
+        let cases = [
+            ("Berlin, 13.08.2025", DateOrder::Dmy, date(2025, 8, 13)),
+            (
+                "Berlin, den 13. August 2025",
+                DateOrder::Dmy,
+                date(2025, 8, 13),
+            ),
+            ("Berlin, 13. Aug. 2025", DateOrder::Dmy, date(2025, 8, 13)),
+            (
+                "New York, August 13, 2025",
+                DateOrder::Mdy,
+                date(2025, 8, 13),
+            ),
+            ("Aug 13th 2025", DateOrder::Dmy, date(2025, 8, 13)),
+            (
+                "Paris, le 1er janvier 2025",
+                DateOrder::Dmy,
+                date(2025, 1, 1),
+            ),
+            ("Wien, 3. Jänner 2025", DateOrder::Dmy, date(2025, 1, 3)),
+            ("Date: 2025-08-13", DateOrder::Dmy, date(2025, 8, 13)),
+            ("Date: 2025/8/3", DateOrder::Mdy, date(2025, 8, 3)),
+            // numeric dates are read in the configured order
+            ("03/04/2025", DateOrder::Dmy, date(2025, 4, 3)),
+            ("03/04/2025", DateOrder::Mdy, date(2025, 3, 4)),
+            ("03-04-2025", DateOrder::Mdy, date(2025, 3, 4)),
+            // except with dots, always day first
+            ("03.04.2025", DateOrder::Mdy, date(2025, 4, 3)),
+            // and when only the other order makes a date
+            ("08/13/2025", DateOrder::Dmy, date(2025, 8, 13)),
+            ("13/08/2025", DateOrder::Mdy, date(2025, 8, 13)),
+            // two-digit years are 1970 to 2069
+            ("13.08.25", DateOrder::Dmy, date(2025, 8, 13)),
+            ("13/08/99", DateOrder::Dmy, date(1999, 8, 13)),
+            ("01.02.69", DateOrder::Dmy, None),
+            // the first date that is not in the future
+            (
+                "Due 01.12.2030, sent 13.08.2025",
+                DateOrder::Dmy,
+                date(2025, 8, 13),
+            ),
+            (
+                "13.08.2025, regarding 01.01.2024",
+                DateOrder::Dmy,
+                date(2025, 8, 13),
+            ),
+            ("Valid until 31.12.2099", DateOrder::Dmy, None),
+            ("Sent 2025-10-19", DateOrder::Dmy, None),
+            ("Sent 2025-10-18", DateOrder::Dmy, date(2025, 10, 18)),
+            // not dates
+            ("Version 1.2.24", DateOrder::Dmy, None),
+            ("Release 1.2.24.3", DateOrder::Dmy, None),
+            ("Firmware 10.12.2024.1", DateOrder::Dmy, None),
+            (
+                "Build 3.10.2024.7 of 13.08.2025",
+                DateOrder::Dmy,
+                date(2025, 8, 13),
+            ),
+            ("13.08-2025", DateOrder::Dmy, None),
+            ("31.02.2025", DateOrder::Dmy, None),
+            ("In 1850 or 1.1.1850", DateOrder::Dmy, None),
+            ("No date at all", DateOrder::Dmy, None),
+        ];
+
+        for (text, order, expected) in cases {
+            assert_eq!(
+                find_date(text, order, today),
+                expected,
+                "{} ({:?})",
+                text,
+                order
+            );
+        }
+    }
+
+    #[test]
+    fn parses_the_date_order() {
+        assert_eq!("MDY".parse(), Ok(DateOrder::Mdy));
+        assert_eq!(" dmy ".parse(), Ok(DateOrder::Dmy));
+        assert!("ymd".parse::<DateOrder>().is_err());
+    }
+}
@@ -0,0 +1,137 @@
+// Server-sent events so the UI can follow ingestion without polling
+
+use std::convert::Infallible;
+
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::models::{Document, Task};
+
+/// How many events a slow subscriber may fall behind before it starts missing them.
+const EVENT_BUFFER: usize = 256;
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TaskUpdated { task: Box<Task> },
+    DocumentAdded { document: DocumentSummary },
+    DocumentDeleted { id: String },
+    IndexCommitted { opstamp: u64 },
+}
+
+/// What the UI needs to list a new document. Its text can be megabytes, so it is left out, as
+/// every subscriber gets a copy of each event.
+#[derive(Clone, Serialize)]
+pub struct DocumentSummary {
+    pub id: String,
+    pub title: String,
+    pub thumbnail_url: String,
+    pub created: Option<NaiveDate>,
+    pub added: NaiveDateTime,
+}
+
+impl From<&Document> for DocumentSummary {
+    fn from(document: &Document) -> Self {
+        DocumentSummary {
+            id: document.id.clone(),
+            title: document.title.clone(),
+            thumbnail_url: document.thumbnail_path(),
+            created: document.created,
+            added: document.added,
+        }
+    }
+}
+
+impl Event {
+    /// The SSE event name, matching the serialised `type`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TaskUpdated { .. } => "task_updated",
+            Event::DocumentAdded { .. } => "document_added",
+            Event::DocumentDeleted { .. } => "document_deleted",
+            Event::IndexCommitted { .. } => "index_committed",
+        }
+    }
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER);
+        EventBus { sender }
+    }
+
+    /// Send an event to every connected client. Nobody listening is not an error.
+    pub fn publish(&self, event: Event) {
+        let _ = self.sender.send(event);
+    }
+
+    /// A never-ending SSE stream of everything published from now on.
+    pub fn sse(&self) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>> + use<>> {
+        let stream =
+            BroadcastStream::new(self.sender.subscribe()).filter_map(|event| match event {
+                Ok(event) => match SseEvent::default().event(event.name()).json_data(&event) {
+                    Ok(sse_event) => Some(Ok(sse_event)),
+                    Err(e) => {
+                        println!("Failed to serialise {} event: {}", event.name(), e);
+                        None
+                    }
+                },
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    println!("SSE subscriber lagged behind, skipped {} events", skipped);
+                    None
+                }
+            });
+
+        Sse::new(stream).keep_alive(KeepAlive::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_are_announced_without_their_text() {
+        let document = Document {
+            id: "abc".to_string(),
+            title: "Invoice".to_string(),
+            body: "a very long text".repeat(1000),
+            thumbnail_url: String::new(),
+            checksum: None,
+            mime_type: "application/pdf".to_string(),
+            extension: "pdf".to_string(),
+            parent_id: None,
+            created: NaiveDate::from_ymd_opt(2025, 8, 13),
+            added: chrono::DateTime::UNIX_EPOCH.naive_utc(),
+            correspondent_id: None,
+            document_type_id: None,
+        };
+
+        let event = Event::DocumentAdded {
+            document: (&document).into(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "type": "document_added",
+                "document": {
+                    "id": "abc",
+                    "title": "Invoice",
+                    "thumbnail_url": "/api/docs/abc/pages/1/image?width=400",
+                    "created": "2025-08-13",
+                    "added": "1970-01-01T00:00:00",
+                },
+            })
+        );
+    }
+}
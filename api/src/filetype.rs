@@ -0,0 +1,47 @@
+// What an upload is, as told by the parser that recognises it from its first bytes
+
+use std::io::Read;
+use std::path::Path;
+
+/// A kind of file a parser handles. Each parser declares its own, so a new format does not
+/// need to be known anywhere else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileType {
+    pub mime_type: &'static str,
+    /// Known extensions, the usual one first.
+    pub extensions: &'static [&'static str],
+}
+
+/// How much of a file parsers get to look at to tell its type.
+pub const SNIFF_LEN: usize = 8 * 1024;
+
+/// The first [`SNIFF_LEN`] bytes of a file, or all of it if it is shorter.
+pub fn read_header(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    std::fs::File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+impl FileType {
+    pub fn has_extension(&self, filename: &str) -> bool {
+        lowercase_extension(filename)
+            .is_some_and(|extension| self.extensions.contains(&extension.as_str()))
+    }
+
+    /// The extension of `filename` if it fits this type (so `scan.jpeg` stays `jpeg`), the usual
+    /// extension otherwise.
+    pub fn extension_for(&self, filename: &str) -> String {
+        match lowercase_extension(filename) {
+            Some(extension) if self.extensions.contains(&extension.as_str()) => extension,
+            _ => self.extensions[0].to_string(),
+        }
+    }
+}
+
+fn lowercase_extension(filename: &str) -> Option<String> {
+    Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+}
@@ -0,0 +1,253 @@
+// Opening the Tantivy index and turning documents into index entries
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+use chrono::NaiveTime;
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl, SelectableHelper,
+};
+use tantivy::directory::MmapDirectory;
+use tantivy::schema::{Facet, OwnedValue, Schema};
+use tantivy::{Index, IndexSettings, IndexWriter, TantivyDocument, Term};
+
+use crate::models::{Document, DocumentPage, Mail, Tag};
+use crate::schema::{
+    correspondents, document_pages, document_tags, document_types, documents, mails,
+};
+use crate::{INDEX_PATH_RAW, PgPool, custom_fields, tag_tree};
+
+pub type IndexResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Open the index, or create it if there is none yet or its schema is outdated. Also returns
+/// whether the index is new, in which case it has to be rebuilt from the database.
+pub fn open(schema: Schema) -> tantivy::Result<(Index, bool)> {
+    let dir = Path::new(INDEX_PATH_RAW);
+    let directory = MmapDirectory::open(dir)?;
+
+    if Index::exists(&directory)? {
+        let index = Index::open(directory)?;
+
+        if index.schema() == schema {
+            return Ok((index, false));
+        }
+
+        println!("The index schema changed, rebuilding the index");
+
+        // an index cannot change its schema, so start over
+        drop(index);
+        std::fs::remove_dir_all(dir)?;
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let directory = MmapDirectory::open(dir)?;
+    let index = Index::create(directory, schema, IndexSettings::default())?;
+
+    Ok((index, true))
+}
+
+/// What is stored next to a document and indexed with it.
+pub struct Related {
+    pub mail: Option<Mail>,
+    /// The paths of its tags, e.g. `["Finance", "Tax"]`.
+    pub tag_paths: Vec<Vec<String>>,
+    /// The name of its correspondent.
+    pub correspondent: Option<String>,
+    /// The name of its type.
+    pub document_type: Option<String>,
+    /// Its custom field values by the name of their field.
+    pub custom_fields: BTreeMap<String, OwnedValue>,
+}
+
+/// The index entry of a document, with the headers of a mail in their own fields and its tags
+/// as facets.
+pub fn to_tantivy(
+    schema: &Schema,
+    doc: &Document,
+    related: &Related,
+) -> tantivy::Result<TantivyDocument> {
+    let mut entry = TantivyDocument::new();
+    entry.add_text(schema.get_field("title")?, &doc.title);
+    entry.add_text(schema.get_field("id")?, &doc.id);
+    entry.add_text(schema.get_field("body")?, &doc.body);
+
+    if let Some(created) = doc.created {
+        entry.add_date(
+            schema.get_field("created")?,
+            tantivy::DateTime::from_timestamp_secs(
+                created.and_time(NaiveTime::MIN).and_utc().timestamp(),
+            ),
+        );
+    }
+
+    for path in &related.tag_paths {
+        entry.add_facet(schema.get_field("tags")?, Facet::from_path(path));
+    }
+
+    if let Some(correspondent) = &related.correspondent {
+        entry.add_text(schema.get_field("correspondent")?, correspondent);
+    }
+
+    if let Some(document_type) = &related.document_type {
+        entry.add_text(schema.get_field("type")?, document_type);
+    }
+
+    if !related.custom_fields.is_empty() {
+        entry.add_object(schema.get_field("custom")?, related.custom_fields.clone());
+    }
+
+    if let Some(mail) = &related.mail {
+        let headers = [
+            ("from", &mail.from_address),
+            ("to", &mail.to_address),
+            ("subject", &mail.subject),
+        ];
+
+        for (name, value) in headers {
+            if let Some(value) = value {
+                entry.add_text(schema.get_field(name)?, value);
+            }
+        }
+
+        if let Some(sent_at) = mail.sent_at {
+            entry.add_date(
+                schema.get_field("date")?,
+                tantivy::DateTime::from_timestamp_secs(sent_at.and_utc().timestamp()),
+            );
+        }
+    }
+
+    Ok(entry)
+}
+
+/// An index entry per page, next to the one of the document, so a search can tell which pages
+/// matched. Page entries share the `id` of their document, so deleting it deletes them too.
+pub fn page_entries(
+    schema: &Schema,
+    pages: &[DocumentPage],
+) -> tantivy::Result<Vec<TantivyDocument>> {
+    pages
+        .iter()
+        .map(|page| {
+            let mut entry = TantivyDocument::new();
+            entry.add_text(schema.get_field("id")?, &page.document_id);
+            entry.add_u64(schema.get_field("page")?, page.page_number as u64);
+            entry.add_text(schema.get_field("page_body")?, &page.body);
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// All index entries of a document, as stored in the database. `all_tags` is every tag there
+/// is, to find the paths of the tags of the document.
+pub fn entries(
+    conn: &mut PgConnection,
+    schema: &Schema,
+    doc: &Document,
+    all_tags: &[Tag],
+) -> IndexResult<Vec<TantivyDocument>> {
+    let mail: Option<Mail> = mails::table
+        .find(&doc.id)
+        .select(Mail::as_select())
+        .first(conn)
+        .optional()?;
+
+    let pages: Vec<DocumentPage> = document_pages::table
+        .filter(document_pages::document_id.eq(&doc.id))
+        .order(document_pages::page_number)
+        .select(DocumentPage::as_select())
+        .load(conn)?;
+
+    let tag_paths: Vec<Vec<String>> = document_tags::table
+        .filter(document_tags::document_id.eq(&doc.id))
+        .select(document_tags::tag_id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .map(|tag_id| tag_tree::path(all_tags, tag_id))
+        .collect();
+
+    let correspondent: Option<String> = match doc.correspondent_id {
+        Some(id) => correspondents::table
+            .find(id)
+            .select(correspondents::name)
+            .first(conn)
+            .optional()?,
+        None => None,
+    };
+
+    let document_type: Option<String> = match doc.document_type_id {
+        Some(id) => document_types::table
+            .find(id)
+            .select(document_types::name)
+            .first(conn)
+            .optional()?,
+        None => None,
+    };
+
+    let related = Related {
+        mail,
+        tag_paths,
+        correspondent,
+        document_type,
+        custom_fields: custom_fields::index_values(conn, &doc.id)?,
+    };
+
+    let mut entries = vec![to_tantivy(schema, doc, &related)?];
+    entries.extend(page_entries(schema, &pages)?);
+
+    Ok(entries)
+}
+
+/// Replace the entries of the documents with what is in the database now, e.g. after their
+/// tags changed. Documents that no longer exist are only removed. Needs a commit afterwards.
+pub fn replace(
+    conn: &mut PgConnection,
+    schema: &Schema,
+    writer: &mut IndexWriter,
+    document_ids: &[String],
+) -> IndexResult<()> {
+    let id_field = schema.get_field("id")?;
+    let all_tags = tag_tree::load_all(conn)?;
+
+    for id in document_ids {
+        writer.delete_term(Term::from_field_text(id_field, id));
+
+        let doc: Option<Document> = documents::table
+            .find(id)
+            .select(Document::as_select())
+            .first(conn)
+            .optional()?;
+
+        if let Some(doc) = doc {
+            for entry in entries(conn, schema, &doc, &all_tags)? {
+                writer.add_document(entry)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Add every document in the database to an empty index.
+pub fn rebuild(pool: &PgPool, schema: &Schema, writer: &mut IndexWriter) -> IndexResult<()> {
+    let mut conn = pool.get()?;
+
+    let docs: Vec<Document> = documents::table
+        .select(Document::as_select())
+        .load(&mut conn)?;
+
+    let all_tags = tag_tree::load_all(&mut conn)?;
+
+    for doc in &docs {
+        for entry in entries(&mut conn, schema, doc, &all_tags)? {
+            writer.add_document(entry)?;
+        }
+    }
+
+    writer.commit()?;
+
+    println!("Indexed {} documents", docs.len());
+
+    Ok(())
+}
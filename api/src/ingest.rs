@@ -0,0 +1,312 @@
+// The ingestion pipeline run by the worker for every queued upload
+
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::Local;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::{Connection, ExpressionMethods, QueryDsl, RunQueryDsl};
+use tempfile::NamedTempFile;
+
+use crate::AppState;
+use crate::events::Event;
+use crate::models::{DocumentPage, Mail, Task, TaskStatus};
+use crate::ocr::OcrConfig;
+use crate::parsers::{Source, THUMBNAIL_WIDTH};
+use crate::queue::{self, DuplicatePolicy, QueueError, UploadOptions};
+use crate::schema::{document_pages, documents, mails};
+use crate::{dates, index, matching, tag_tree, utils, worker};
+
+pub type IngestResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Extract the text of the staged upload (with OCR where needed), store the original, the
+/// archive and a thumbnail in S3, insert the document into the database and index it. The
+/// index is only committed once everything else succeeded. How each step is done depends on
+/// the parser for the type of the upload, and attachments it finds are queued as children.
+pub async fn ingest(state: Arc<AppState>, task: Task) -> IngestResult<()> {
+    let original = Path::new(&task.file_path);
+    let id = &task.document_id;
+
+    let (parser, file_type) = state
+        .parsers
+        .sniff(original, &task.filename)?
+        .ok_or("Unsupported file type")?;
+
+    let ocr_config = OcrConfig {
+        mode: task.ocr_mode.parse()?,
+        languages: task.ocr_languages.clone(),
+    };
+
+    let progress = |status| set_status(&state, &task, status);
+
+    // the one sent with the upload first
+    let passwords: Vec<String> = task
+        .password
+        .iter()
+        .chain(state.pdf_passwords.iter())
+        .cloned()
+        .collect();
+
+    let source = Source {
+        path: original,
+        file_type,
+        ocr_config: &ocr_config,
+        passwords: &passwords,
+        progress: &progress,
+    };
+
+    let metadata = parser.metadata(&source)?;
+
+    // kept alive until the end, it is deleted when dropped
+    let converted = parser.to_pdf(&source).await?;
+
+    // everything from here on works on a PDF
+    let path = converted
+        .as_ref()
+        .map(|file| file.path())
+        .unwrap_or(original);
+
+    let pages = parser.extract_text(&source, path).await?;
+
+    // the whole text is kept too, with pages separated by form feeds as `pdftotext` does
+    let contents = pages.join("\x0c");
+
+    println!(
+        "Managed to get the contents: {}",
+        contents
+            .split_whitespace()
+            .take(10)
+            .collect::<Vec<&str>>()
+            .join(" ")
+    );
+
+    let archive = NamedTempFile::new()?;
+    let archive_path = match parser.archive(&source, path, archive.path()).await {
+        Ok(()) => archive.path(),
+        Err(e) => {
+            println!(
+                "Failed to create archive, storing the unprocessed PDF instead: {}",
+                e
+            );
+            path
+        }
+    };
+
+    set_status(&state, &task, TaskStatus::Thumbnailing);
+
+    let img_buf = parser.thumbnail(&source, path)?;
+
+    let buf = utils::encode_webp(&img_buf)?;
+
+    // the date a mail was sent beats any date in its text
+    let created = metadata
+        .mail
+        .as_ref()
+        .and_then(|mail| mail.date)
+        .map(|date| date.date())
+        .or_else(|| dates::find_date(&contents, state.date_order, Local::now().date_naive()));
+
+    let (correspondent_id, document_type_id, matched_tags) = {
+        let mut conn = state.db_pool.get()?;
+        (
+            matching::correspondent_for(&mut conn, &contents)?,
+            matching::document_type_for(&mut conn, &contents)?,
+            matching::tags_for(&mut conn, &contents)?,
+        )
+    };
+
+    let new_doc = crate::models::Document {
+        id: id.clone(),
+        title: metadata
+            .title
+            .clone()
+            .unwrap_or_else(|| task.filename.clone()),
+        body: contents.clone(),
+        thumbnail_url: String::from(""), // TODO: this will be a presigned-url
+        checksum: task.checksum.clone(),
+        mime_type: file_type.mime_type.to_string(),
+        extension: file_type.extension_for(&task.filename),
+        parent_id: task.parent_id.clone(),
+        created,
+        added: task.created_at,
+        correspondent_id,
+        document_type_id,
+    };
+
+    {
+        let s3_client = state.s3_client.lock().await;
+
+        s3_client
+            .upload_object(
+                file_type.mime_type,
+                &new_doc.original_key(),
+                ByteStream::from_path(original).await?,
+            )
+            .await
+            .map_err(|e| format!("Failed to upload to s3: {}", e))?;
+
+        s3_client
+            .upload_object(
+                "application/pdf",
+                &format!("{}/archive.pdf", id),
+                ByteStream::from_path(archive_path).await?,
+            )
+            .await
+            .map_err(|e| format!("Failed to upload to s3: {}", e))?;
+
+        // where the page image endpoint caches the first page at this width
+        s3_client
+            .upload_object(
+                "image/webp",
+                &new_doc.page_image_key(1, THUMBNAIL_WIDTH),
+                ByteStream::from(buf),
+            )
+            .await
+            .map_err(|e| format!("Failed to upload to s3: {}", e))?;
+    }
+
+    set_status(&state, &task, TaskStatus::Indexing);
+
+    let mail_row = metadata.mail.map(|mail| Mail {
+        document_id: id.clone(),
+        from_address: mail.from,
+        to_address: mail.to,
+        subject: mail.subject,
+        sent_at: mail.date,
+        message_id: mail.message_id,
+    });
+
+    let page_rows: Vec<DocumentPage> = pages
+        .into_iter()
+        .enumerate()
+        .map(|(index, body)| DocumentPage {
+            document_id: id.clone(),
+            page_number: index as i32 + 1,
+            body,
+        })
+        .collect();
+
+    let mut conn = state.db_pool.get()?;
+
+    // a task interrupted mid-way is run again, so the rows of an earlier attempt are replaced
+    conn.transaction::<_, DieselError, _>(|conn| {
+        diesel::insert_into(documents::table)
+            .values(&new_doc)
+            .on_conflict(documents::id)
+            .do_update()
+            .set(&new_doc)
+            .execute(conn)?;
+
+        diesel::delete(mails::table.find(id)).execute(conn)?;
+        diesel::delete(document_pages::table.filter(document_pages::document_id.eq(id)))
+            .execute(conn)?;
+
+        if let Some(mail_row) = &mail_row {
+            diesel::insert_into(mails::table)
+                .values(mail_row)
+                .execute(conn)?;
+        }
+
+        diesel::insert_into(document_pages::table)
+            .values(&page_rows)
+            .execute(conn)?;
+
+        // tags are given by name, or by path for nested ones, and created as needed
+        let mut tag_ids = task
+            .tags
+            .iter()
+            .filter(|path| !path.trim().is_empty())
+            .map(|path| tag_tree::find_or_create(conn, path))
+            .collect::<Result<Vec<i32>, DieselError>>()?;
+
+        // and those whose matching rule picked the document, `assign` skips duplicates
+        tag_ids.extend(&matched_tags);
+
+        tag_tree::assign(conn, id, &tag_ids)?;
+
+        Ok(())
+    })
+    .map_err(|e| -> Box<dyn Error + Send + Sync> {
+        match e {
+            // another upload of the same file got ingested in the meantime
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                "Duplicate of an existing document".into()
+            }
+            e => e.into(),
+        }
+    })?;
+
+    // replacing rather than adding, in case an earlier attempt got as far as the index
+    let opstamp = {
+        let mut index_writer = state.writer.lock().await;
+        index::replace(
+            &mut conn,
+            &state.schema,
+            &mut index_writer,
+            &[id.to_string()],
+        )?;
+        index_writer.commit()?
+    };
+
+    state.events.publish(Event::DocumentAdded {
+        document: (&new_doc).into(),
+    });
+    state.events.publish(Event::IndexCommitted { opstamp });
+
+    if !metadata.attachments.is_empty() {
+        queue_attachments(&state, &task, metadata.attachments, &ocr_config).await;
+    }
+
+    Ok(())
+}
+
+/// Queue the attachments of a document (e.g. the PDFs attached to a mail) as documents of their
+/// own, linked to it. A failing attachment does not fail the document.
+async fn queue_attachments(
+    state: &AppState,
+    task: &Task,
+    attachments: Vec<(String, Vec<u8>)>,
+    ocr_config: &OcrConfig,
+) {
+    let options = UploadOptions {
+        ocr_config: ocr_config.clone(),
+        on_duplicate: DuplicatePolicy::Reject,
+        parent_id: Some(task.document_id.clone()),
+        password: None,
+    };
+
+    for (filename, bytes) in attachments {
+        let upload = match queue::stage_bytes(filename.clone(), &bytes, task.tags.clone()).await {
+            Ok(upload) => upload,
+            Err(e) => {
+                println!("Failed to stage attachment `{}`: {}", filename, e);
+                continue;
+            }
+        };
+
+        // one by one, so an attachment we already have does not hold up the others
+        match queue::enqueue(state, vec![upload], &options) {
+            Ok(_) => println!("Queued attachment `{}` of {}", filename, task.document_id),
+            Err(QueueError::Duplicate { document_id, .. }) => {
+                println!(
+                    "Attachment `{}` is a duplicate of {}",
+                    filename, document_id
+                )
+            }
+            Err(QueueError::Internal(e)) => {
+                println!("Failed to queue attachment `{}`: {}", filename, e)
+            }
+        }
+    }
+}
+
+fn set_status(state: &AppState, task: &Task, status: TaskStatus) {
+    match worker::update_status(&state.db_pool, &task.id, status) {
+        Ok(task) => state.events.publish(Event::TaskUpdated {
+            task: Box::new(task),
+        }),
+        Err(e) => println!("Failed to update the status of task {}: {}", task.id, e),
+    }
+}
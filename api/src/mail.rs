@@ -0,0 +1,709 @@
+// Periodically fetch mail over IMAP and ingest PDF attachments (or the mail itself) according to
+// the mail rules stored in the database.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_imap::Session;
+use async_imap::types::Fetch;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper};
+use futures::TryStreamExt;
+use mail_parser::{MessageParser, MimeHeaders};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::AppState;
+use crate::models::{MailAccount, MailRule};
+use crate::parsers;
+use crate::queue::{self, DuplicatePolicy, QueueError, Upload, UploadOptions};
+use crate::schema::{mail_accounts, mail_rules};
+use crate::utils;
+
+pub type MailResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Mails that matched a rule but had nothing to ingest, by rule id and UID. They are not
+/// acted upon, so this keeps them from being downloaded over and over again.
+type Skipped = HashSet<(i32, u32)>;
+
+pub async fn run(state: Arc<AppState>, interval: Duration) {
+    let mut skipped = Skipped::new();
+
+    loop {
+        if let Err(e) = fetch_all(&state, &mut skipped).await {
+            println!("Failed to fetch mail: {}", e);
+        }
+
+        tokio::select! {
+            _ = state.mail_notify.notified() => {}
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+async fn fetch_all(state: &AppState, skipped: &mut Skipped) -> MailResult<()> {
+    let (accounts, rules) = {
+        let mut conn = state.db_pool.get()?;
+
+        let accounts: Vec<MailAccount> = mail_accounts::table
+            .select(MailAccount::as_select())
+            .load(&mut conn)?;
+
+        let rules: Vec<MailRule> = mail_rules::table
+            .filter(mail_rules::enabled.eq(true))
+            .order(mail_rules::id.asc())
+            .select(MailRule::as_select())
+            .load(&mut conn)?;
+
+        (accounts, rules)
+    };
+
+    let mut rules_by_account: HashMap<i32, Vec<MailRule>> = HashMap::new();
+    for rule in rules {
+        rules_by_account
+            .entry(rule.account_id)
+            .or_default()
+            .push(rule);
+    }
+
+    for account in accounts {
+        let Some(rules) = rules_by_account.get(&account.id) else {
+            continue;
+        };
+
+        if let Err(e) = fetch_account(state, &account, rules, skipped).await {
+            println!("Failed to fetch mail for `{}`: {}", account.name, e);
+        }
+    }
+
+    Ok(())
+}
+
+async fn fetch_account(
+    state: &AppState,
+    account: &MailAccount,
+    rules: &[MailRule],
+    skipped: &mut Skipped,
+) -> MailResult<()> {
+    let port = u16::try_from(account.port)?;
+    let tcp = TcpStream::connect((account.host.as_str(), port)).await?;
+
+    match account.security.as_str() {
+        // plain IMAP, e.g. a test server on localhost
+        "none" => process_account(state, account, rules, skipped, tcp).await,
+        _ => {
+            let tls = async_native_tls::TlsConnector::new()
+                .connect(&account.host, tcp)
+                .await?;
+            process_account(state, account, rules, skipped, tls).await
+        }
+    }
+}
+
+async fn process_account<T>(
+    state: &AppState,
+    account: &MailAccount,
+    rules: &[MailRule],
+    skipped: &mut Skipped,
+    stream: T,
+) -> MailResult<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    let client = async_imap::Client::new(stream);
+
+    let mut session = client
+        .login(&account.username, password(account)?)
+        .await
+        .map_err(|(e, _)| e)?;
+
+    for rule in rules {
+        if let Err(e) = process_rule(state, &mut session, rule, skipped).await {
+            println!("Mail rule `{}` failed: {}", rule.name, e);
+        }
+    }
+
+    session.logout().await?;
+
+    Ok(())
+}
+
+/// The password of an account. Passwords are stored in the database as they are, so one can be
+/// given as `env:NAME` instead, to read it from that environment variable.
+fn password(account: &MailAccount) -> MailResult<String> {
+    match account.password.strip_prefix("env:") {
+        Some(name) => std::env::var(name).map_err(|_| {
+            format!(
+                "The environment variable `{}` with the password is not set",
+                name
+            )
+            .into()
+        }),
+        None => Ok(account.password.clone()),
+    }
+}
+
+async fn process_rule<T>(
+    state: &AppState,
+    session: &mut Session<T>,
+    rule: &MailRule,
+    skipped: &mut Skipped,
+) -> MailResult<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    let options = UploadOptions {
+        ocr_config: state.ocr_config.clone(),
+        on_duplicate: DuplicatePolicy::Reject,
+        parent_id: None,
+        password: None,
+    };
+
+    process_mails(session, rule, skipped, |upload| {
+        queue::enqueue(state, vec![upload], &options).map(|_| ())
+    })
+    .await
+}
+
+/// Hand what is to be ingested from the mails matching a rule to `enqueue`, and act upon the
+/// mails that were queued in full.
+async fn process_mails<T>(
+    session: &mut Session<T>,
+    rule: &MailRule,
+    skipped: &mut Skipped,
+    mut enqueue: impl FnMut(Upload) -> Result<(), QueueError>,
+) -> MailResult<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    session.select(&rule.folder).await?;
+
+    let mut uids: Vec<u32> = session
+        .uid_search(search_query(rule))
+        .await?
+        .into_iter()
+        .collect();
+    uids.sort_unstable();
+
+    for uid in uids {
+        if skipped.contains(&(rule.id, uid)) {
+            continue;
+        }
+
+        // one message at a time, so a mailbox full of large attachments is never all in memory
+        let fetches: Vec<Fetch> = session
+            .uid_fetch(uid.to_string(), "BODY.PEEK[]")
+            .await?
+            .try_collect()
+            .await?;
+
+        let Some(raw) = fetches.iter().find_map(|fetch| fetch.body()) else {
+            continue;
+        };
+
+        let uploads = mail_uploads(rule, raw).await?;
+
+        if uploads.is_empty() {
+            skipped.insert((rule.id, uid));
+            continue;
+        }
+
+        let mut queued_all = true;
+
+        for upload in uploads {
+            let filename = upload.filename.clone();
+
+            // queued one by one, so an attachment we already have does not hold up the others
+            match enqueue(upload) {
+                Ok(_) => println!("Queued `{}` from mail rule `{}`", filename, rule.name),
+                Err(QueueError::Duplicate { document_id, .. }) => {
+                    println!("`{}` is a duplicate of {}, skipping", filename, document_id)
+                }
+                Err(QueueError::Internal(e)) => {
+                    println!("Failed to queue `{}`: {}", filename, e);
+                    queued_all = false;
+                }
+            }
+        }
+
+        // otherwise the mail is left alone so it gets picked up again next time
+        if queued_all {
+            apply_action(session, rule, uid).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// IMAP search criteria for a rule. Mails that were already acted upon no longer match.
+fn search_query(rule: &MailRule) -> String {
+    let mut criteria = vec![
+        match rule.action.as_str() {
+            "mark_read" => "UNSEEN",
+            "flag" => "UNFLAGGED",
+            // moved and deleted mails are gone from the folder
+            _ => "ALL",
+        }
+        .to_string(),
+    ];
+
+    if let Some(from) = &rule.filter_from {
+        criteria.push(format!("FROM {}", quote(from)));
+    }
+
+    if let Some(subject) = &rule.filter_subject {
+        criteria.push(format!("SUBJECT {}", quote(subject)));
+    }
+
+    if let Some(days) = rule.max_age_days {
+        let since = chrono::Utc::now() - chrono::Duration::days(days.into());
+        criteria.push(format!("SINCE {}", since.format("%d-%b-%Y")));
+    }
+
+    criteria.join(" ")
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The files to ingest from a raw message, depending on the scope of the rule.
+async fn mail_uploads(rule: &MailRule, raw: &[u8]) -> MailResult<Vec<Upload>> {
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return Ok(Vec::new());
+    };
+
+    let mut uploads = Vec::new();
+
+    match rule.scope.as_str() {
+        "body" => {
+            let subject = message.subject().unwrap_or("mail");
+            let filename = format!("{}.pdf", sanitize_filename(subject));
+
+            let text = parsers::mail_text(&message);
+            let pdf = tokio::task::spawn_blocking(move || utils::text_to_pdf(&text))
+                .await?
+                .map_err(|e| format!("Failed to render mail: {}", e))?;
+
+            uploads.push(queue::stage_bytes(filename, &pdf, rule.tags.clone()).await?);
+        }
+        _ => {
+            for part in message.attachments() {
+                if !parsers::is_pdf_attachment(part) {
+                    continue;
+                }
+
+                let filename = part
+                    .attachment_name()
+                    .map(sanitize_filename)
+                    .unwrap_or_else(|| "attachment.pdf".to_string());
+
+                uploads
+                    .push(queue::stage_bytes(filename, part.contents(), rule.tags.clone()).await?);
+            }
+        }
+    }
+
+    Ok(uploads)
+}
+
+fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .take(120)
+        .collect()
+}
+
+async fn apply_action<T>(session: &mut Session<T>, rule: &MailRule, uid: u32) -> MailResult<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    let uid = uid.to_string();
+
+    match rule.action.as_str() {
+        "flag" => {
+            let _: Vec<Fetch> = session
+                .uid_store(&uid, "+FLAGS (\\Flagged)")
+                .await?
+                .try_collect()
+                .await?;
+        }
+        "move" => {
+            let folder = rule
+                .action_parameter
+                .as_deref()
+                .ok_or("The `move` action needs a target folder")?;
+            session.uid_mv(&uid, folder).await?;
+        }
+        "delete" => {
+            let _: Vec<Fetch> = session
+                .uid_store(&uid, "+FLAGS (\\Deleted)")
+                .await?
+                .try_collect()
+                .await?;
+            let _: Vec<u32> = session.expunge().await?.try_collect().await?;
+        }
+        _ => {
+            let _: Vec<Fetch> = session
+                .uid_store(&uid, "+FLAGS (\\Seen)")
+                .await?
+                .try_collect()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    use super::*;
+
+    struct Message {
+        uid: u32,
+        from: &'static str,
+        subject: &'static str,
+        raw: String,
+        flags: Vec<String>,
+    }
+
+    /// What the stub server holds and what it was asked for.
+    #[derive(Default)]
+    struct Mailbox {
+        messages: Vec<Message>,
+        fetched: Vec<u32>,
+        searches: Vec<String>,
+        moved: Vec<(u32, String)>,
+    }
+
+    fn message(uid: u32, from: &'static str, subject: &'static str, pdf: Option<&str>) -> Message {
+        let body = match pdf {
+            Some(name) => format!(
+                "Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+                 --b\r\nContent-Type: text/plain\r\n\r\nSee attached.\r\n\
+                 --b\r\nContent-Type: application/pdf\r\n\
+                 Content-Disposition: attachment; filename=\"{}\"\r\n\
+                 Content-Transfer-Encoding: base64\r\n\r\nJVBERi0xLjQgc3R1Yg==\r\n--b--\r\n",
+                name
+            ),
+            None => "Content-Type: text/plain\r\n\r\nNothing attached.\r\n".to_string(),
+        };
+
+        Message {
+            uid,
+            from,
+            subject,
+            raw: format!("From: {}\r\nSubject: {}\r\n{}", from, subject, body),
+            flags: Vec::new(),
+        }
+    }
+
+    /// Split a command into its words and quoted strings.
+    fn words(line: &str) -> Vec<String> {
+        let mut words = Vec::new();
+        let mut chars = line.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                ' ' => {}
+                '"' => {
+                    let mut word = String::new();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => word.extend(chars.next()),
+                            '"' => break,
+                            c => word.push(c),
+                        }
+                    }
+                    words.push(word);
+                }
+                c => {
+                    let mut word = c.to_string();
+                    while let Some(c) = chars.next_if(|c| *c != ' ') {
+                        word.push(c);
+                    }
+                    words.push(word);
+                }
+            }
+        }
+
+        words
+    }
+
+    fn search(mailbox: &Mailbox, criteria: &[String]) -> Vec<u32> {
+        mailbox
+            .messages
+            .iter()
+            .filter(|message| {
+                let mut criteria = criteria.iter();
+                let mut matches = true;
+
+                while let Some(key) = criteria.next() {
+                    let flagged = |flag: &str| message.flags.iter().any(|f| f == flag);
+                    let contains = |header: &str, value: Option<&String>| {
+                        value.is_some_and(|value| {
+                            header.to_lowercase().contains(&value.to_lowercase())
+                        })
+                    };
+
+                    matches &= match key.as_str() {
+                        "UNSEEN" => !flagged("\\Seen"),
+                        "UNFLAGGED" => !flagged("\\Flagged"),
+                        "FROM" => contains(message.from, criteria.next()),
+                        "SUBJECT" => contains(message.subject, criteria.next()),
+                        "SINCE" => criteria.next().is_some(),
+                        _ => true,
+                    };
+                }
+
+                matches
+            })
+            .map(|message| message.uid)
+            .collect()
+    }
+
+    /// Answer the commands the mail rules send, like an IMAP server with one folder would.
+    async fn serve(stream: DuplexStream, mailbox: Arc<Mutex<Mailbox>>) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut lines = BufReader::new(reader).lines();
+
+        writer.write_all(b"* OK stub ready\r\n").await.unwrap();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            let words = words(&line);
+            let tag = &words[0];
+            let command = words[1..]
+                .iter()
+                .take(2)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let mut response = String::new();
+            let mut status = "OK done";
+
+            {
+                let mut mailbox = mailbox.lock().unwrap();
+                let seq = |mailbox: &Mailbox, uid: u32| {
+                    mailbox.messages.iter().position(|m| m.uid == uid).unwrap() + 1
+                };
+
+                match command.as_str() {
+                    c if c.starts_with("LOGIN") => {}
+                    c if c.starts_with("SELECT") => {
+                        response = format!("* {} EXISTS\r\n", mailbox.messages.len());
+                    }
+                    c if c.starts_with("LOGOUT") => response = "* BYE\r\n".to_string(),
+                    "UID SEARCH" => {
+                        mailbox.searches.push(words[3..].join(" "));
+                        let uids: Vec<String> = search(&mailbox, &words[3..])
+                            .iter()
+                            .map(u32::to_string)
+                            .collect();
+                        response = format!("* SEARCH {}\r\n", uids.join(" "));
+                    }
+                    "UID FETCH" => {
+                        let uid: u32 = words[3].parse().unwrap();
+                        mailbox.fetched.push(uid);
+                        let seq = seq(&mailbox, uid);
+                        let raw = &mailbox.messages[seq - 1].raw;
+                        response = format!(
+                            "* {} FETCH (UID {} BODY[] {{{}}}\r\n{})\r\n",
+                            seq,
+                            uid,
+                            raw.len(),
+                            raw
+                        );
+                    }
+                    "UID STORE" => {
+                        let uid: u32 = words[3].parse().unwrap();
+                        let flag = words[5].trim_matches(|c| c == '(' || c == ')').to_string();
+                        let seq = seq(&mailbox, uid);
+                        mailbox.messages[seq - 1].flags.push(flag.clone());
+                        response = format!("* {} FETCH (UID {} FLAGS ({}))\r\n", seq, uid, flag);
+                    }
+                    "UID MOVE" => {
+                        let uid: u32 = words[3].parse().unwrap();
+                        let seq = seq(&mailbox, uid);
+                        mailbox.messages.remove(seq - 1);
+                        mailbox.moved.push((uid, words[4].clone()));
+                    }
+                    _ => status = "BAD unknown command",
+                }
+            }
+
+            response.push_str(&format!("{} {}\r\n", tag, status));
+
+            writer.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn rule(action: &str) -> MailRule {
+        MailRule {
+            id: 1,
+            account_id: 1,
+            name: "invoices".to_string(),
+            folder: "INBOX".to_string(),
+            filter_from: None,
+            filter_subject: None,
+            max_age_days: None,
+            scope: "attachments".to_string(),
+            action: action.to_string(),
+            action_parameter: None,
+            tags: vec!["mail".to_string()],
+            enabled: true,
+        }
+    }
+
+    /// Run a rule against the stub server, returning the names and contents of what got queued.
+    async fn run_rule(
+        mailbox: &Arc<Mutex<Mailbox>>,
+        rule: &MailRule,
+        skipped: &mut Skipped,
+        fail: bool,
+    ) -> Vec<(String, Vec<u8>)> {
+        std::fs::create_dir_all(crate::UPLOAD_PATH_RAW).unwrap();
+
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(serve(server, mailbox.clone()));
+
+        let mut session = async_imap::Client::new(client)
+            .login("user", "secret")
+            .await
+            .map_err(|(e, _)| e)
+            .unwrap();
+
+        let mut queued = Vec::new();
+        process_mails(&mut session, rule, skipped, |upload| {
+            if fail {
+                return Err(QueueError::Internal("database is down".to_string()));
+            }
+            assert_eq!(upload.tags, rule.tags);
+            let contents = std::fs::read(upload.staged.path()).unwrap();
+            queued.push((upload.filename, contents));
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        session.logout().await.unwrap();
+        drop(session);
+        server.await.unwrap();
+
+        queued
+    }
+
+    fn mailbox() -> Arc<Mutex<Mailbox>> {
+        Arc::new(Mutex::new(Mailbox {
+            messages: vec![
+                message(1, "billing@acme.com", "Invoice 1", Some("invoice-1.pdf")),
+                message(2, "someone@else.org", "Invoice 2", Some("invoice-2.pdf")),
+                message(3, "billing@acme.com", "Hello", None),
+            ],
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn queues_the_pdf_attachments_of_matching_mails_and_marks_them_read() {
+        let mailbox = mailbox();
+        let mut rule = rule("mark_read");
+        rule.filter_from = Some("billing@acme.com".to_string());
+        let mut skipped = Skipped::new();
+
+        let queued = run_rule(&mailbox, &rule, &mut skipped, false).await;
+
+        assert_eq!(
+            queued,
+            vec![("invoice-1.pdf".to_string(), b"%PDF-1.4 stub".to_vec())]
+        );
+
+        {
+            let mailbox = mailbox.lock().unwrap();
+            assert_eq!(mailbox.searches, vec!["UNSEEN FROM billing@acme.com"]);
+            assert_eq!(mailbox.fetched, vec![1, 3]);
+            assert_eq!(mailbox.messages[0].flags, vec!["\\Seen"]);
+            // nothing to ingest, so it is not marked read but remembered as skipped
+            assert!(mailbox.messages[2].flags.is_empty());
+            assert!(mailbox.messages[1].flags.is_empty());
+        }
+        assert!(skipped.contains(&(rule.id, 3)));
+
+        // read mails no longer match, and skipped ones are not downloaded again
+        let queued = run_rule(&mailbox, &rule, &mut skipped, false).await;
+
+        assert!(queued.is_empty());
+        assert_eq!(mailbox.lock().unwrap().fetched, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn moves_mails_filtered_by_subject() {
+        let mailbox = mailbox();
+        let mut rule = rule("move");
+        rule.filter_subject = Some("invoice".to_string());
+        rule.action_parameter = Some("Archive".to_string());
+
+        let queued = run_rule(&mailbox, &rule, &mut Skipped::new(), false).await;
+
+        let names: Vec<&str> = queued.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["invoice-1.pdf", "invoice-2.pdf"]);
+
+        let mailbox = mailbox.lock().unwrap();
+        assert_eq!(mailbox.searches, vec!["ALL SUBJECT invoice"]);
+        assert_eq!(
+            mailbox.moved,
+            vec![(1, "Archive".to_string()), (2, "Archive".to_string())]
+        );
+        assert_eq!(mailbox.messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn leaves_mails_alone_when_queueing_fails() {
+        let mailbox = mailbox();
+        let mut skipped = Skipped::new();
+
+        let queued = run_rule(&mailbox, &rule("mark_read"), &mut skipped, true).await;
+
+        assert!(queued.is_empty());
+        assert!(
+            mailbox
+                .lock()
+                .unwrap()
+                .messages
+                .iter()
+                .all(|message| message.flags.is_empty())
+        );
+        // so they are tried again next time
+        assert!(!skipped.contains(&(1, 1)));
+    }
+
+    #[test]
+    fn reads_passwords_from_the_environment() {
+        let mut account = MailAccount {
+            id: 1,
+            name: "mail".to_string(),
+            host: "localhost".to_string(),
+            port: 143,
+            security: "none".to_string(),
+            username: "user".to_string(),
+            password: "secret".to_string(),
+            created_at: chrono::DateTime::UNIX_EPOCH.naive_utc(),
+        };
+
+        assert_eq!(password(&account).unwrap(), "secret");
+
+        account.password = "env:CARGO_MANIFEST_DIR".to_string();
+        assert_eq!(password(&account).unwrap(), env!("CARGO_MANIFEST_DIR"));
+
+        account.password = "env:PAPERS_NO_SUCH_VARIABLE".to_string();
+        assert!(password(&account).is_err());
+    }
+}
@@ -1,46 +1,90 @@
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::Client;
 use aws_sdk_s3::config::Builder as S3ConfigBuilder;
+use aws_sdk_s3::operation::get_object::GetObjectOutput;
 use aws_sdk_s3::primitives::ByteStream;
-use axum::body::{Body, Bytes};
-use axum::extract::{Query, State, multipart};
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, Query, State, multipart};
 use axum::http::{HeaderValue, StatusCode};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, patch, post};
 use axum::{Json, Router};
+use chrono::NaiveDate;
 use diesel::r2d2::{ConnectionManager, Pool};
-use diesel::{PgConnection, QueryDsl, RunQueryDsl, TextExpressionMethods};
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, PgSortExpressionMethods,
+    QueryDsl, RunQueryDsl, SelectableHelper, TextExpressionMethods,
+};
 use dotenvy::dotenv;
-use image::codecs::png::PngEncoder;
-use image::{ExtendedColorType, ImageEncoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::env;
 use std::ops::DerefMut;
 use std::sync::Arc;
+use std::time::Duration;
 use tantivy::collector::{Count, TopDocs};
-use tantivy::directory::MmapDirectory;
-use tantivy::query::QueryParser;
-use tantivy::schema::{Schema, Value, STORED, STRING, TEXT};
+use tantivy::query::{BooleanQuery, Occur, QueryParser, TermQuery};
+use tantivy::schema::{
+    FAST, FacetOptions, INDEXED, IndexRecordOption, STORED, STRING, Schema, TEXT, Value,
+};
 use tantivy::{Document, IndexReader, TantivyDocument, Term};
 use tantivy::{Index, IndexWriter, ReloadPolicy};
-use tempfile::NamedTempFile;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio_util::io::ReaderStream;
 use tower_http::cors::Any;
 use tower_http::cors::CorsLayer;
 
+use crate::consumer::ConsumerConfig;
+use crate::dates::DateOrder;
+use crate::events::{Event, EventBus};
+use crate::models::{
+    Correspondent, CustomField, CustomFieldChanges, CustomFieldValue, DocumentType, DocumentView,
+    FieldValue, Mail, MailAccount, MailRule, NewCustomField, NewMailAccount, NewMailRule, Task,
+};
+use crate::ocr::OcrConfig;
+use crate::parsers::ParserRegistry;
+use crate::queue::{DuplicatePolicy, QueueError, Upload, UploadOptions};
 use crate::s3::S3Client;
-use crate::schema::documents;
-
+use crate::schema::{
+    correspondents, custom_fields as custom_fields_table, document_types, documents, mail_accounts,
+    mail_rules, mails, tasks,
+};
+
+mod classifiers;
+mod consumer;
+mod custom_fields;
+mod dates;
+mod events;
+mod filetype;
+mod index;
+mod ingest;
+mod mail;
+mod matching;
 mod models;
+mod ocr;
+mod parsers;
+mod queue;
 mod s3;
 mod schema;
+mod tag_tree;
 mod utils;
+mod worker;
 
 type PgPool = Pool<ConnectionManager<PgConnection>>;
 
 static INDEX_PATH_RAW: &str = "tmp/index";
+static UPLOAD_PATH_RAW: &str = "tmp/uploads";
+static DEFAULT_MAX_UPLOAD_SIZE: u64 = 100 * 1024 * 1024;
+static DEFAULT_MAIL_POLL_INTERVAL: u64 = 300;
+static DEFAULT_PAGE_IMAGE_WIDTH: u16 = 1200;
+/// The widths pages are rendered at. Other widths get the next one up (or the largest), so
+/// only a few renders of each page end up cached.
+static PAGE_IMAGE_WIDTHS: [u16; 4] = [400, 800, 1200, 2000];
+/// The upload has no body limit, so every field but `file` is read up to this many bytes.
+static MAX_TEXT_FIELD_SIZE: usize = 4 * 1024;
 struct AppState {
     index: Index,
     schema: Schema,
@@ -48,6 +92,20 @@ struct AppState {
     reader: IndexReader,
     db_pool: PgPool,
     s3_client: Mutex<S3Client>,
+    ocr_config: OcrConfig,
+    /// Turn uploads into text, archives and thumbnails by their type.
+    parsers: ParserRegistry,
+    /// Wakes the ingestion worker when a new task is queued.
+    task_notify: Notify,
+    /// Wakes the mail fetcher for a run outside its schedule.
+    mail_notify: Notify,
+    events: EventBus,
+    /// Largest accepted file in bytes, from `MAX_UPLOAD_SIZE`.
+    max_upload_size: u64,
+    /// Passwords to try on encrypted PDFs, from `PDF_PASSWORDS`.
+    pdf_passwords: Vec<String>,
+    /// How to read numeric dates when looking for the date of a document.
+    date_order: DateOrder,
 }
 
 #[tokio::main]
@@ -74,10 +132,6 @@ async fn main() -> tantivy::Result<()> {
         Err(e) => println!("Error with bucket existing: {}", e),
     }
 
-    let index_dir = std::path::Path::new(INDEX_PATH_RAW);
-
-    let index_dir = MmapDirectory::open(index_dir)?;
-
     let mut schema_builder = Schema::builder();
 
     schema_builder.add_text_field("title", TEXT | STORED);
@@ -86,14 +140,43 @@ async fn main() -> tantivy::Result<()> {
 
     schema_builder.add_text_field("body", TEXT);
 
+    // headers of mails
+    schema_builder.add_text_field("from", TEXT | STORED);
+    schema_builder.add_text_field("to", TEXT | STORED);
+    schema_builder.add_text_field("subject", TEXT | STORED);
+    schema_builder.add_date_field("date", INDEXED | STORED);
+
+    // one entry per page next to the one of the document, see `index::page_entries`
+    schema_builder.add_u64_field("page", INDEXED | STORED);
+    schema_builder.add_text_field("page_body", TEXT);
+
+    // the date the document was written, fast to sort by it
+    schema_builder.add_date_field("created", INDEXED | STORED | FAST);
+
+    // by path, so `tags:/Finance` also finds documents tagged `Finance/Tax`
+    schema_builder.add_facet_field("tags", FacetOptions::default());
+
+    // the name, so searches can say `correspondent:acme`
+    schema_builder.add_text_field("correspondent", TEXT);
+
+    // the name of its type, so searches can say `type:invoice`
+    schema_builder.add_text_field("type", TEXT);
+
+    // custom field values by field name, fast so numbers and dates can be compared
+    schema_builder.add_json_field("custom", TEXT | FAST);
+
     let schema = schema_builder.build();
 
-    let index = Index::open_or_create(index_dir, schema.clone())?;
+    let (index, is_new) = index::open(schema.clone())?;
 
     let mut index_writer: IndexWriter = index.writer(50_000_000)?;
 
     index_writer.commit()?;
 
+    if is_new {
+        index::rebuild(&pool, &schema, &mut index_writer).expect("Failed to rebuild the index");
+    }
+
     let reader = index.reader()?;
 
     let state = Arc::new(AppState {
@@ -103,23 +186,135 @@ async fn main() -> tantivy::Result<()> {
         reader: reader,
         db_pool: pool,
         s3_client: Mutex::<S3Client>::new(s3_client),
+        ocr_config: OcrConfig::from_env(),
+        parsers: ParserRegistry::default(),
+        task_notify: Notify::new(),
+        mail_notify: Notify::new(),
+        events: EventBus::new(),
+        max_upload_size: env::var("MAX_UPLOAD_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE),
+        pdf_passwords: env::var("PDF_PASSWORDS")
+            .map(|passwords| {
+                passwords
+                    .split(',')
+                    .filter(|password| !password.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        date_order: DateOrder::from_env(),
     });
 
+    std::fs::create_dir_all(UPLOAD_PATH_RAW).expect("Failed to create the upload directory");
+
+    worker::spawn(Arc::clone(&state));
+
+    if let Some(consumer_config) = ConsumerConfig::from_env() {
+        tokio::spawn(consumer::run(Arc::clone(&state), consumer_config));
+    }
+
+    let mail_poll_interval = env::var("MAIL_POLL_INTERVAL")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_MAIL_POLL_INTERVAL);
+
+    tokio::spawn(mail::run(
+        Arc::clone(&state),
+        Duration::from_secs(mail_poll_interval),
+    ));
+
     let document_routes: Router<()> = Router::new()
         .route("/", get(get_all_docs))
         .route("/download/{id}", get(download_doc))
         .route("/preview/{id}", get(preview_doc))
-        .route("/upload", post(save_and_upsert))
+        .route("/{id}/pages/{page}/image", get(get_page_image))
+        .route(
+            "/upload",
+            // the size limits are enforced per field while reading it, see `read_text_field`
+            post(save_and_upsert).layer(DefaultBodyLimit::disable()),
+        )
         .route("/delete/{id}", delete(delete_doc))
+        .route("/by-checksum/{checksum}", get(get_doc_by_checksum))
+        .route("/children/{id}", get(get_doc_children))
+        .route("/mail/{id}", get(get_doc_mail))
+        .route("/{id}", patch(update_doc))
+        .with_state(Arc::clone(&state));
+
+    let custom_field_routes: Router<()> = Router::new()
+        .route("/", get(get_custom_fields).post(create_custom_field))
+        .route(
+            "/{id}",
+            patch(update_custom_field).delete(delete_custom_field),
+        )
+        .with_state(Arc::clone(&state));
+
+    let document_type_routes: Router<()> = Router::new()
+        .route(
+            "/",
+            get(classifiers::list::<DocumentType>).post(classifiers::create::<DocumentType>),
+        )
+        .route(
+            "/{id}",
+            patch(classifiers::update::<DocumentType>).delete(classifiers::delete::<DocumentType>),
+        )
+        .with_state(Arc::clone(&state));
+
+    let correspondent_routes: Router<()> = Router::new()
+        .route(
+            "/",
+            get(classifiers::list::<Correspondent>).post(classifiers::create::<Correspondent>),
+        )
+        .route(
+            "/{id}",
+            patch(classifiers::update::<Correspondent>)
+                .delete(classifiers::delete::<Correspondent>),
+        )
+        .with_state(Arc::clone(&state));
+
+    let tag_routes: Router<()> = Router::new()
+        .route(
+            "/",
+            get(classifiers::tags::list).post(classifiers::tags::create),
+        )
+        .route(
+            "/{id}",
+            patch(classifiers::tags::update).delete(classifiers::tags::delete),
+        )
         .with_state(Arc::clone(&state));
 
     let search_routes: Router<()> = Router::new()
         .route("/", get(find_matches))
         .with_state(Arc::clone(&state));
 
+    let task_routes: Router<()> = Router::new()
+        .route("/", get(get_all_tasks))
+        .route("/{id}", get(get_task))
+        .with_state(Arc::clone(&state));
+
+    let mail_routes: Router<()> = Router::new()
+        .route(
+            "/accounts",
+            get(get_mail_accounts).post(create_mail_account),
+        )
+        .route("/accounts/{id}", delete(delete_mail_account))
+        .route("/rules", get(get_mail_rules).post(create_mail_rule))
+        .route("/rules/{id}", delete(delete_mail_rule))
+        .route("/run", post(run_mail))
+        .with_state(Arc::clone(&state));
+
     let api_routes = Router::new()
+        .route("/events", get(stream_events))
+        .with_state(Arc::clone(&state))
         .nest("/search", search_routes)
-        .nest("/docs", document_routes);
+        .nest("/docs", document_routes)
+        .nest("/tasks", task_routes)
+        .nest("/mail", mail_routes)
+        .nest("/tags", tag_routes)
+        .nest("/correspondents", correspondent_routes)
+        .nest("/document_types", document_type_routes)
+        .nest("/custom_fields", custom_field_routes);
 
     let app = Router::new().nest("/api", api_routes).layer(
         CorsLayer::new()
@@ -136,13 +331,57 @@ async fn main() -> tantivy::Result<()> {
     Ok(())
 }
 
+#[derive(Deserialize)]
+struct FileParams {
+    /// Serve the untouched upload instead of the OCR'd archive version.
+    #[serde(default)]
+    original: bool,
+}
+
+/// The document with this id, if there is one.
+fn find_doc(state: &AppState, id: &str) -> Option<crate::models::Document> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    documents::table
+        .find(id)
+        .select(crate::models::Document::as_select())
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load document")
+}
+
+/// Fetch the archive version of a document, falling back to the original for documents that
+/// were ingested before archives existed. Also returns the extension of the file served, as
+/// the original may be an image.
+async fn get_doc_file(
+    s3_client: &S3Client,
+    doc: &crate::models::Document,
+    original: bool,
+) -> Result<(GetObjectOutput, String), Box<dyn std::error::Error>> {
+    if !original {
+        match s3_client
+            .get_object(&format!("{}/archive.pdf", doc.id))
+            .await
+        {
+            Ok(out) => return Ok((out, "pdf".to_string())),
+            Err(e) => println!("No archive for {}, serving the original: {}", doc.id, e),
+        }
+    }
+
+    let out = s3_client.get_object(&doc.original_key()).await?;
+
+    Ok((out, doc.extension.clone()))
+}
+
 async fn download_doc(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(id): axum::extract::Path<String>,
-) -> impl IntoResponse {
+    Query(params): Query<FileParams>,
+) -> Result<Response, StatusCode> {
+    let doc = find_doc(&state, &id).ok_or(StatusCode::NOT_FOUND)?;
+
     let s3_client = &mut state.s3_client.lock().await;
-    let out = s3_client
-        .get_object(format!("{}/document.pdf", id).as_ref())
+    let (out, extension) = get_doc_file(s3_client, &doc, params.original)
         .await
         .expect("Expected URL");
 
@@ -155,11 +394,12 @@ async fn download_doc(
 
     let axum_body = Body::from_stream(stream);
 
-    Response::builder()
+    Ok(Response::builder()
         .status(StatusCode::OK)
         .header(
             axum::http::header::CONTENT_DISPOSITION,
-            HeaderValue::from_str("attachment; filename=\"document.pdf\"").unwrap(),
+            HeaderValue::from_str(&format!("attachment; filename=\"document.{}\"", extension))
+                .unwrap(),
         )
         .header(axum::http::header::CONTENT_TYPE, content_type)
         .header(
@@ -167,16 +407,18 @@ async fn download_doc(
             content_length.expect("Expected content length").to_string(),
         )
         .body(axum_body)
-        .unwrap()
+        .unwrap())
 }
 
 async fn preview_doc(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(id): axum::extract::Path<String>,
-) -> impl IntoResponse {
+    Query(params): Query<FileParams>,
+) -> Result<Response, StatusCode> {
+    let doc = find_doc(&state, &id).ok_or(StatusCode::NOT_FOUND)?;
+
     let s3_client = &mut state.s3_client.lock().await;
-    let out = s3_client
-        .get_object(format!("{}/document.pdf", id).as_ref())
+    let (out, extension) = get_doc_file(s3_client, &doc, params.original)
         .await
         .expect("Expected URL");
 
@@ -189,11 +431,11 @@ async fn preview_doc(
 
     let axum_body = Body::from_stream(stream);
 
-    Response::builder()
+    Ok(Response::builder()
         .status(StatusCode::OK)
         .header(
             axum::http::header::CONTENT_DISPOSITION,
-            HeaderValue::from_static(r#"inline; filename="preview.pdf""#),
+            HeaderValue::from_str(&format!("inline; filename=\"preview.{}\"", extension)).unwrap(),
         )
         .header(axum::http::header::CONTENT_TYPE, content_type)
         .header(
@@ -201,122 +443,315 @@ async fn preview_doc(
             content_length.expect("Expected content length").to_string(),
         )
         .body(axum_body)
-        .unwrap()
+        .unwrap())
 }
 
-/// We get the multipart form data as a stream of fields, to avoid overloading RAM for large files,
-/// we will save to disk as we receive them and build the tantivy::document in memory, we only
-/// commit once we have all the data for atomicity.
-async fn save_and_upsert(State(state): State<Arc<AppState>>, mut multipart: multipart::Multipart) {
-    let tmp = NamedTempFile::new().expect("Expected a tempfile"); // created on disk
-    let path: &std::path::Path = tmp.path();
+#[derive(Deserialize)]
+struct PageImageParams {
+    /// In pixels, the height follows from the page.
+    width: Option<u16>,
+}
 
-    println!("Path: {}", path.display());
+/// A page of the archive (counted from 1) rendered as a WebP image. Renders are cached in S3
+/// per page and width, so each is only rendered once.
+async fn get_page_image(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path((id, page)): axum::extract::Path<(String, u16)>,
+    Query(params): Query<PageImageParams>,
+) -> Result<Response, StatusCode> {
+    let width = page_image_width(params.width.unwrap_or(DEFAULT_PAGE_IMAGE_WIDTH));
 
-    let mut doc = TantivyDocument::new();
-    let schema = &state.schema;
+    if page == 0 {
+        return Err(StatusCode::NOT_FOUND);
+    }
 
-    let title_field = schema.get_field("title").expect("Expected a title field");
-    let id_field = schema.get_field("id").expect("Expected an id field");
-    let body_field = schema.get_field("body").expect("Expected a body field");
+    let doc = find_doc(&state, &id).ok_or(StatusCode::NOT_FOUND)?;
+    let key = doc.page_image_key(page, width);
+
+    let cached = state.s3_client.lock().await.get_object(&key).await.ok();
+
+    if let Some(out) = cached {
+        let stream = ReaderStream::new(out.body.into_async_read());
+        return Ok(page_image_response(Body::from_stream(stream)));
+    }
+
+    let (out, _) = {
+        let s3_client = state.s3_client.lock().await;
+        get_doc_file(&s3_client, &doc, false).await
+    }
+    .map_err(|e| {
+        println!("Failed to get the archive of {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // pdfium reads from disk, so the archive is streamed to a file chunk by chunk, without
+    // holding on to the client, which other requests wait for
+    let pdf = download_to_temp_file(out.body).await.map_err(|e| {
+        println!("Failed to download the archive of {}: {}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    // rendering blocks
+    let image = tokio::task::spawn_blocking(move || {
+        let Some(image) = utils::render_pdf_page(&pdf.path(), page - 1, width, None)
+            .map_err(|e| e.to_string())?
+        else {
+            return Ok(None);
+        };
+
+        utils::encode_webp(&image)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .expect("Failed to join the render task")
+    .map_err(|e| {
+        println!("Failed to render page {} of {}: {}", page, id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    // a failed upload only means rendering it again next time
+    if let Err(e) = state
+        .s3_client
+        .lock()
+        .await
+        .upload_object("image/webp", &key, ByteStream::from(image.clone()))
+        .await
+    {
+        println!("Failed to cache {}: {}", key, e);
+    }
+
+    Ok(page_image_response(Body::from(image)))
+}
+
+/// Write an S3 object to a temporary file as it comes in, so only a chunk of it is ever held in
+/// memory.
+async fn download_to_temp_file(body: ByteStream) -> std::io::Result<tempfile::NamedTempFile> {
+    let pdf = tempfile::NamedTempFile::new()?;
+    let mut file = tokio::fs::File::create(pdf.path()).await?;
+
+    tokio::io::copy(&mut body.into_async_read(), &mut file).await?;
+    file.flush().await?;
+
+    Ok(pdf)
+}
+
+fn page_image_width(requested: u16) -> u16 {
+    PAGE_IMAGE_WIDTHS
+        .iter()
+        .copied()
+        .find(|width| *width >= requested)
+        .unwrap_or(PAGE_IMAGE_WIDTHS[PAGE_IMAGE_WIDTHS.len() - 1])
+}
+
+fn page_image_response(body: Body) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(axum::http::header::CONTENT_TYPE, "image/webp")
+        // the archive of a document never changes
+        .header(axum::http::header::CACHE_CONTROL, "public, max-age=86400")
+        .body(body)
+        .unwrap()
+}
+
+fn multipart_error(e: multipart::MultipartError) -> (StatusCode, String) {
+    (e.status(), e.body_text())
+}
 
-    let mut set_field = false;
+/// Write a multipart field to `path` chunk by chunk, so only one chunk is ever held in memory,
+/// and return its length and hex-encoded SHA-256 checksum. Fails with `413 Payload Too Large`
+/// as soon as the field grows past `max_size` bytes.
+async fn stream_to_file(
+    field: &mut multipart::Field<'_>,
+    path: &std::path::Path,
+    max_size: u64,
+) -> Result<(u64, String), (StatusCode, String)> {
+    let io_error = |e: std::io::Error| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+
+    let mut file = tokio::fs::File::create(path).await.map_err(io_error)?;
+    let mut length: u64 = 0;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        length += chunk.len() as u64;
+        hasher.update(&chunk);
+
+        if length > max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload exceeds the maximum size of {} bytes", max_size),
+            ));
+        }
 
-    let id = uuid::Uuid::new_v4().to_string();
+        file.write_all(&chunk).await.map_err(io_error)?;
+    }
 
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        let filename = field.file_name().unwrap().to_string();
+    file.flush().await.map_err(io_error)?;
 
-        if !set_field {
-            doc.add_text(title_field, &filename);
-            doc.add_text(id_field, id.clone());
-            set_field = true;
+    Ok((length, format!("{:x}", hasher.finalize())))
+}
+
+/// Read a text field of a multipart form chunk by chunk, failing with `413 Payload Too Large`
+/// as soon as it grows past [`MAX_TEXT_FIELD_SIZE`] bytes.
+async fn read_text_field(field: &mut multipart::Field<'_>) -> Result<String, (StatusCode, String)> {
+    let name = field.name().unwrap_or_default().to_string();
+    let mut bytes: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if bytes.len() + chunk.len() > MAX_TEXT_FIELD_SIZE {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Field `{}` exceeds the maximum size of {} bytes",
+                    name, MAX_TEXT_FIELD_SIZE
+                ),
+            ));
         }
 
-        let data: Bytes = field.bytes().await.unwrap();
-
-        println!("Length of `{}` is {} bytes", name, data.len());
-
-        if name == "file" {
-            tokio::fs::write(&path, &data).await.unwrap();
-
-            let contents = utils::pdf_to_string(path).await;
-
-            match utils::export_pdf_to_jpegs(&path, None) {
-                Ok(img_buf) => {
-                    let s3_client = &mut state.s3_client.lock().await;
-
-                    let mut buf = Vec::new();
-
-                    PngEncoder::new(&mut buf)
-                        .write_image(
-                            img_buf.as_raw(),
-                            img_buf.width(),
-                            img_buf.height(),
-                            ExtendedColorType::Rgb8,
-                        )
-                        .unwrap();
-
-                    s3_client
-                        .upload_object(
-                            "application/pdf",
-                            &format!("{}/document.pdf", id),
-                            ByteStream::from_path(path)
-                                .await
-                                .expect("Failed to get bytes from path"),
-                        )
-                        .await
-                        .expect("Failed to upload to s3");
-
-                    s3_client
-                        .upload_object(
-                            "image/png",
-                            &format!("{}/thumbnail.png", id),
-                            ByteStream::from(buf),
-                        )
-                        .await
-                        .expect("Failed to upload to s3");
-
-                    let new_doc = crate::models::Document {
-                        id: id.clone(),
-                        title: filename.clone(),
-                        body: contents.clone(),
-                        thumbnail_url: String::from(""), // TODO: this will be a presigned-url
-                    };
-
-                    let mut conn = state.db_pool.get().expect("Failed to get db connection");
-
-                    diesel::insert_into(documents::table)
-                        .values(&new_doc)
-                        .execute(&mut conn)
-                        .expect("Failed to insert into db");
-                }
-                Err(e) => println!("Failed to export to jpegs: {}", e),
+        bytes.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(bytes).map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Field `{}` is not valid UTF-8", name),
+        )
+    })
+}
+
+#[derive(Serialize)]
+struct DuplicateResponse {
+    error: String,
+    filename: String,
+    document_id: String,
+}
+
+/// We get the multipart form data as a stream of fields, to avoid overloading RAM for large
+/// files, we stream each file to disk chunk by chunk as we receive it. Every `file` field becomes
+/// its own document, queued for the ingestion worker, and the tasks are returned straight away.
+///
+/// Besides the `file` fields, the OCR defaults can be overridden for the whole upload with the
+/// `ocr_mode` (skip, redo, force) and `ocr_languages` (e.g. `eng+deu+nld`) fields. Files that
+/// were uploaded before are rejected with `409 Conflict`, unless `on_duplicate` is `link`, in
+/// which case their task points straight at the existing document. Encrypted PDFs can be sent
+/// with a `password`, which is tried before the ones configured in `PDF_PASSWORDS`. `tags`
+/// takes comma-separated tag names or paths like `Finance/Tax`, created if they do not exist.
+async fn save_and_upsert(
+    State(state): State<Arc<AppState>>,
+    mut multipart: multipart::Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    // staged next to the queue so they survive a restart, but only once the upload is accepted
+    let mut uploads: Vec<Upload> = Vec::new();
+    let mut options = UploadOptions {
+        ocr_config: state.ocr_config.clone(),
+        on_duplicate: DuplicatePolicy::default(),
+        parent_id: None,
+        password: None,
+    };
+    let mut upload_tags: Vec<String> = Vec::new();
+
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let name = field.name().unwrap_or_default().to_string();
+
+        match name.as_str() {
+            "file" => {
+                let filename = field.file_name().unwrap_or("document.pdf").to_string();
+
+                let staged = queue::staging_file().expect("Expected a tempfile");
+
+                println!("Path: {}", staged.path().display());
+
+                let (length, checksum) =
+                    stream_to_file(&mut field, staged.path(), state.max_upload_size).await?;
+
+                println!("Length of `{}` is {} bytes", filename, length);
+
+                uploads.push(Upload {
+                    filename,
+                    staged,
+                    checksum,
+                    tags: Vec::new(),
+                });
+            }
+            "ocr_mode" => {
+                let value = read_text_field(&mut field).await?;
+                options.ocr_config.mode =
+                    value.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            }
+            "ocr_languages" => {
+                let value = read_text_field(&mut field).await?;
+                options.ocr_config.languages =
+                    ocr::parse_languages(&value).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
             }
+            "on_duplicate" => {
+                let value = read_text_field(&mut field).await?;
+                options.on_duplicate = value.parse().map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+            }
+            "password" => {
+                let value = read_text_field(&mut field).await?;
+                options.password = Some(value).filter(|password| !password.is_empty());
+            }
+            "tags" => {
+                let value = read_text_field(&mut field).await?;
+                upload_tags.extend(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|path| !path.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            _ => println!("Ignoring unknown field `{}`", name),
+        }
+    }
 
-            println!(
-                "Managed to get the contents: {}",
-                contents
-                    .split_whitespace()
-                    .take(10)
-                    .collect::<Vec<&str>>()
-                    .join(" ")
-            );
+    if uploads.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Missing `file` field".to_string()));
+    }
+
+    for path in &upload_tags {
+        path.split('/')
+            .try_for_each(tag_tree::validate_name)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
 
-            doc.add_text(body_field, contents);
+    for upload in uploads.iter_mut() {
+        upload.tags.extend(upload_tags.iter().cloned());
+    }
+
+    match queue::enqueue(&state, uploads, &options) {
+        Ok(queued) => Ok((StatusCode::ACCEPTED, Json(queued)).into_response()),
+        Err(QueueError::Duplicate {
+            filename,
+            document_id,
+        }) => {
+            let response = DuplicateResponse {
+                error: "Duplicate of an existing document".to_string(),
+                filename,
+                document_id,
+            };
+
+            Ok((StatusCode::CONFLICT, Json(response)).into_response())
         }
+        Err(QueueError::Internal(e)) => Err((StatusCode::INTERNAL_SERVER_ERROR, e)),
     }
+}
 
-    let mut index_writer = state.writer.lock().await;
-    index_writer.add_document(doc).unwrap();
-    index_writer.commit().unwrap();
+/// A document matching a search, with the page that matches best if the match is in its text.
+#[derive(Serialize)]
+struct SearchHit {
+    id: String,
+    title: String,
+    page: Option<u64>,
+    /// Opens the preview at the matching page.
+    url: String,
 }
 
 async fn find_matches(
     State(state): State<Arc<AppState>>,
     Query(params): Query<HashMap<String, String>>,
-) -> Json<Vec<String>> {
+) -> Result<Json<Vec<SearchHit>>, (StatusCode, String)> {
     let index = &state.index;
     let schema = &state.schema;
 
@@ -327,19 +762,48 @@ async fn find_matches(
     let searcher = reader.searcher();
 
     let title = schema.get_field("title").expect("Expected a title field");
+    let id = schema.get_field("id").expect("Expected an id field");
     let body = schema.get_field("body").expect("Expected a body field");
-
-    let query_term = params.get("query").unwrap();
+    let from = schema.get_field("from").expect("Expected a from field");
+    let to = schema.get_field("to").expect("Expected a to field");
+    let subject = schema
+        .get_field("subject")
+        .expect("Expected a subject field");
+    let correspondent = schema
+        .get_field("correspondent")
+        .expect("Expected a correspondent field");
+    let document_type = schema.get_field("type").expect("Expected a type field");
+    let page = schema.get_field("page").expect("Expected a page field");
+    let page_body = schema
+        .get_field("page_body")
+        .expect("Expected a page_body field");
+
+    let query_term = params.get("query").ok_or((
+        StatusCode::BAD_REQUEST,
+        "Missing the `query` parameter".to_string(),
+    ))?;
 
     println!("Query term: {}", query_term);
 
-    // let query = utils::simple_fuzzy_query(title, body, &query_term).unwrap();
-    let mut query_parser = QueryParser::for_index(&index, vec![title, body]);
+    // custom fields are searched by their name alone
+    let fields = {
+        let mut conn = state.db_pool.get().expect("Failed to get db connection");
+        custom_fields::load_all(&mut conn).expect("Failed to load custom fields")
+    };
+    let query_term = &custom_fields::rewrite_query(query_term, &fields);
 
+    // let query = utils::simple_fuzzy_query(title, body, &query_term).unwrap();
+    let mut query_parser = QueryParser::for_index(
+        &index,
+        vec![title, body, from, to, subject, correspondent, document_type],
+    );
     query_parser.set_conjunction_by_default();
 
-    let query = query_parser.parse_query(&query_term).unwrap();
+    let query = query_parser
+        .parse_query(query_term)
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid query: {}", e)))?;
 
+    // page entries have none of the fields above, so only documents match
     let (top_docs, _) = searcher
         .search(&query, &(TopDocs::with_limit(5), Count))
         .unwrap();
@@ -349,35 +813,81 @@ async fn find_matches(
         println!("score {score:?} doc {}", retrieved_doc.to_json(&schema));
     }
 
-    // use functional programming to return a vector of the document titles
+    // the terms may be spread over several pages, the page with most of them wins
+    let page_query = QueryParser::for_index(index, vec![page_body])
+        .parse_query(query_term)
+        .ok();
 
-    let top_docs = top_docs
+    let hits = top_docs
         .iter()
-        .map(|(_score, doc_address)| searcher.doc(*doc_address).unwrap())
-        .collect::<Vec<TantivyDocument>>();
-
-    return Json(
-        top_docs
-            .iter()
-            .filter_map(|doc| match doc.get_first(title) {
-                Some(title) => Some(title.as_str().expect("Expected a title").to_string()),
-                None => None,
+        .map(|(_score, doc_address)| searcher.doc::<TantivyDocument>(*doc_address).unwrap())
+        .filter_map(|doc| {
+            let doc_id = doc.get_first(id)?.as_str()?.to_string();
+            let doc_title = doc.get_first(title)?.as_str()?.to_string();
+
+            let best_page = page_query.as_ref().and_then(|page_query| {
+                let query = BooleanQuery::new(vec![
+                    (
+                        Occur::Must,
+                        Box::new(TermQuery::new(
+                            Term::from_field_text(id, &doc_id),
+                            IndexRecordOption::Basic,
+                        )) as Box<dyn tantivy::query::Query>,
+                    ),
+                    (Occur::Must, page_query.box_clone()),
+                ]);
+
+                let (_score, address) = searcher
+                    .search(&query, &TopDocs::with_limit(1))
+                    .unwrap()
+                    .into_iter()
+                    .next()?;
+
+                searcher
+                    .doc::<TantivyDocument>(address)
+                    .unwrap()
+                    .get_first(page)?
+                    .as_u64()
+            });
+
+            let url = match best_page {
+                Some(number) => format!("/api/docs/preview/{}#page={}", doc_id, number),
+                None => format!("/api/docs/preview/{}", doc_id),
+            };
+
+            Some(SearchHit {
+                id: doc_id,
+                title: doc_title,
+                page: best_page,
+                url,
             })
-            .collect(),
-    );
+        })
+        .collect();
+
+    Ok(Json(hits))
 }
 
 async fn delete_doc(
     State(state): State<Arc<AppState>>,
     axum::extract::Path(id): axum::extract::Path<String>,
 ) -> impl IntoResponse {
+    // without a database row to go by, assume the usual PDF
+    let original_key = find_doc(&state, &id)
+        .map(|doc| doc.original_key())
+        .unwrap_or_else(|| format!("{}/document.pdf", &id));
+
     let s3_client = &mut state.s3_client.lock().await;
+    match s3_client.delete_object(&original_key).await {
+        Ok(_) => println!("Deleted {}", original_key),
+        Err(e) => println!("Error deleting {}: {}", original_key, e),
+    }
+
     match s3_client
-        .delete_object(format!("{}/document.pdf", &id).as_ref())
+        .delete_object(format!("{}/archive.pdf", &id).as_ref())
         .await
     {
-        Ok(_) => println!("Deleted document.pdf"),
-        Err(e) => println!("Error deleting document.pdf: {}", e),
+        Ok(_) => println!("Deleted archive.pdf"),
+        Err(e) => println!("Error deleting archive.pdf: {}", e),
     }
 
     match s3_client
@@ -388,6 +898,21 @@ async fn delete_doc(
         Err(e) => println!("Error deleting thumbnail.png: {}", e),
     }
 
+    // rendered pages, including the thumbnail
+    let page_keys = s3_client
+        .list_keys(&format!("{}/pages/", &id))
+        .await
+        .unwrap_or_else(|e| {
+            println!("Error listing the pages of {}: {}", id, e);
+            Vec::new()
+        });
+
+    for key in page_keys {
+        if let Err(e) = s3_client.delete_object(&key).await {
+            println!("Error deleting {}: {}", key, e);
+        }
+    }
+
     let mut conn = state.db_pool.get().expect("Failed to get db connection");
 
     diesel::delete(documents::table.filter(documents::id.like(&id)))
@@ -404,31 +929,703 @@ async fn delete_doc(
 
     index_writer.delete_term(term);
 
-    index_writer
+    let opstamp = index_writer
         .commit()
         .expect("Failed to commit index deletion");
 
     let reader = &state.reader;
     reader.reload().unwrap();
 
+    state.events.publish(Event::DocumentDeleted { id });
+    state.events.publish(Event::IndexCommitted { opstamp });
+
     (StatusCode::OK, "Deleted document")
 }
 
-async fn get_all_docs(State(state): State<Arc<AppState>>) -> Json<Vec<crate::models::Document>> {
+#[derive(Deserialize)]
+struct DocumentParams {
+    /// `created`, `added` or `title`, prefixed with `-` for descending order. Newest uploads
+    /// first by default.
+    sort: Option<String>,
+    /// Only documents written on or after this date, e.g. `2025-01-01`.
+    created_from: Option<NaiveDate>,
+    /// Only documents written on or before this date.
+    created_to: Option<NaiveDate>,
+    /// Only documents with this tag or one below it.
+    tag: Option<i32>,
+    /// Only documents from (or to) this correspondent.
+    correspondent: Option<i32>,
+    /// Only documents of this type.
+    document_type: Option<i32>,
+}
+
+async fn get_all_docs(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<DocumentParams>,
+) -> Result<Json<Vec<DocumentView>>, (StatusCode, String)> {
     let mut conn = state.db_pool.get().expect("Failed to get db connection");
-    let s3_client = state.s3_client.lock().await;
-    let mut docs: Vec<crate::models::Document> = documents::table.load(conn.deref_mut()).unwrap();
 
-    for doc in docs.iter_mut() {
-        let url = s3_client
-            .get_object_url(format!("{}/thumbnail.png", doc.id).as_ref(), 60 * 60)
-            .await
-            .expect("Expected URL");
+    let mut query = documents::table
+        .select(crate::models::Document::as_select())
+        .into_boxed();
+
+    if let Some(from) = params.created_from {
+        query = query.filter(documents::created.ge(from));
+    }
+
+    if let Some(to) = params.created_to {
+        query = query.filter(documents::created.le(to));
+    }
+
+    if let Some(correspondent) = params.correspondent {
+        query = query.filter(documents::correspondent_id.eq(correspondent));
+    }
+
+    if let Some(document_type) = params.document_type {
+        query = query.filter(documents::document_type_id.eq(document_type));
+    }
 
-        doc.thumbnail_url = url.clone();
-        println!("URL: {}", url.clone());
+    if let Some(tag) = params.tag {
+        let all_tags = tag_tree::load_all(&mut conn).expect("Failed to load tags");
+        let ids = tag_tree::documents_with(&mut conn, &tag_tree::descendants(&all_tags, tag))
+            .expect("Failed to load tagged documents");
+        query = query.filter(documents::id.eq_any(ids));
     }
-    return Json(docs);
+
+    // documents without a date go last either way
+    query = match params.sort.as_deref().unwrap_or("-added") {
+        "created" => query.order(documents::created.asc().nulls_last()),
+        "-created" => query.order(documents::created.desc().nulls_last()),
+        "added" => query.order(documents::added.asc()),
+        "-added" => query.order(documents::added.desc()),
+        "title" => query.order(documents::title.asc()),
+        "-title" => query.order(documents::title.desc()),
+        other => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Cannot sort by `{}`", other),
+            ));
+        }
+    };
+
+    let docs = query.load(conn.deref_mut()).unwrap();
+
+    Ok(Json(document_views(&mut conn, docs)))
+}
+
+async fn get_doc_by_checksum(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(checksum): axum::extract::Path<String>,
+) -> Result<Json<DocumentView>, StatusCode> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let doc = documents::table
+        .filter(documents::checksum.eq(checksum.to_lowercase()))
+        .select(crate::models::Document::as_select())
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load document")
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let view = document_views(&mut conn, vec![doc]).remove(0);
+
+    Ok(Json(view))
+}
+
+/// Documents as the API returns them, with their thumbnail and tags.
+fn document_views(
+    conn: &mut PgConnection,
+    docs: Vec<crate::models::Document>,
+) -> Vec<DocumentView> {
+    let ids: Vec<String> = docs.iter().map(|doc| doc.id.clone()).collect();
+    let mut tags_by_document = tag_tree::by_document(conn, &ids).expect("Failed to load tags");
+    let mut values_by_document =
+        custom_fields::by_document(conn, &ids).expect("Failed to load custom field values");
+
+    docs.into_iter()
+        .map(|mut doc| {
+            doc.thumbnail_url = doc.thumbnail_path();
+            DocumentView {
+                tags: tags_by_document.remove(&doc.id).unwrap_or_default(),
+                custom_fields: values_by_document.remove(&doc.id).unwrap_or_default(),
+                document: doc,
+            }
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct DocumentChanges {
+    /// Ids of the tags the document should have, replacing the ones it has.
+    tags: Option<Vec<i32>>,
+    /// `null` removes the correspondent.
+    #[serde(default, deserialize_with = "crate::models::nullable")]
+    correspondent_id: Option<Option<i32>>,
+    /// `null` removes the type.
+    #[serde(default, deserialize_with = "crate::models::nullable")]
+    document_type_id: Option<Option<i32>>,
+    /// Values of custom fields, replacing the ones it has.
+    custom_fields: Option<Vec<FieldValue>>,
+}
+
+async fn update_doc(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    Json(changes): Json<DocumentChanges>,
+) -> Result<Json<DocumentView>, (StatusCode, String)> {
+    if find_doc(&state, &id).is_none() {
+        return Err((StatusCode::NOT_FOUND, "No such document".to_string()));
+    }
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    // everything is checked before anything is changed, so a request is applied in full or not
+    // at all
+    let field_values = match &changes.custom_fields {
+        Some(values) => Some(parse_field_values(&state, &mut conn, &id, values)?),
+        None => None,
+    };
+
+    if let Some(Some(correspondent_id)) = changes.correspondent_id {
+        let exists = correspondents::table
+            .find(correspondent_id)
+            .select(correspondents::id)
+            .first::<i32>(&mut conn)
+            .optional()
+            .expect("Failed to load correspondent")
+            .is_some();
+
+        if !exists {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("There is no correspondent {}", correspondent_id),
+            ));
+        }
+    }
+
+    if let Some(Some(document_type_id)) = changes.document_type_id {
+        let exists = document_types::table
+            .find(document_type_id)
+            .select(document_types::id)
+            .first::<i32>(&mut conn)
+            .optional()
+            .expect("Failed to load document type")
+            .is_some();
+
+        if !exists {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("There is no document type {}", document_type_id),
+            ));
+        }
+    }
+
+    if let Some(tag_ids) = &changes.tags {
+        let known: Vec<i32> = tag_tree::load_all(&mut conn)
+            .expect("Failed to load tags")
+            .iter()
+            .map(|tag| tag.id)
+            .collect();
+
+        if let Some(unknown) = tag_ids.iter().find(|tag_id| !known.contains(tag_id)) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("There is no tag {}", unknown),
+            ));
+        }
+    }
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        if let Some(correspondent_id) = changes.correspondent_id {
+            diesel::update(documents::table.find(&id))
+                .set(documents::correspondent_id.eq(correspondent_id))
+                .execute(conn)?;
+        }
+
+        if let Some(document_type_id) = changes.document_type_id {
+            diesel::update(documents::table.find(&id))
+                .set(documents::document_type_id.eq(document_type_id))
+                .execute(conn)?;
+        }
+
+        if let Some(tag_ids) = &changes.tags {
+            tag_tree::assign(conn, &id, tag_ids)?;
+        }
+
+        if let Some(rows) = &field_values {
+            custom_fields::assign(conn, &id, rows)?;
+        }
+
+        Ok(())
+    })
+    .expect("Failed to update document");
+
+    reindex(&state, &mut conn, std::slice::from_ref(&id)).await;
+
+    let doc =
+        find_doc(&state, &id).ok_or((StatusCode::NOT_FOUND, "No such document".to_string()))?;
+    let view = document_views(&mut conn, vec![doc]).remove(0);
+
+    Ok(Json(view))
+}
+
+/// The rows to store for the custom field values of a document, or why they are invalid.
+fn parse_field_values(
+    state: &AppState,
+    conn: &mut PgConnection,
+    document_id: &str,
+    values: &[FieldValue],
+) -> Result<Vec<CustomFieldValue>, (StatusCode, String)> {
+    let fields = custom_fields::load_all(conn).expect("Failed to load custom fields");
+    let mut rows: Vec<CustomFieldValue> = Vec::new();
+
+    for value in values {
+        let field = fields
+            .iter()
+            .find(|field| field.id == value.field)
+            .ok_or_else(|| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("There is no custom field {}", value.field),
+                )
+            })?;
+
+        if rows.iter().any(|row| row.field_id == field.id) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("`{}` is given more than once", field.name),
+            ));
+        }
+
+        let row = custom_fields::parse_value(field, document_id, &value.value)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+        if let Some(row) = row {
+            if let Some(linked) = &row.value_document_id
+                && find_doc(state, linked).is_none()
+            {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("There is no document {}", linked),
+                ));
+            }
+
+            rows.push(row);
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Bring the index entries of the documents up to date with the database.
+async fn reindex(state: &AppState, conn: &mut PgConnection, document_ids: &[String]) {
+    if document_ids.is_empty() {
+        return;
+    }
+
+    let opstamp = {
+        let mut index_writer = state.writer.lock().await;
+
+        index::replace(conn, &state.schema, &mut index_writer, document_ids)
+            .expect("Failed to reindex documents");
+
+        index_writer.commit().expect("Failed to commit the index")
+    };
+
+    state.events.publish(Event::IndexCommitted { opstamp });
+}
+
+/// Documents that came with this one, e.g. the attachments of a mail.
+async fn get_doc_children(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Json<Vec<DocumentView>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let docs: Vec<crate::models::Document> = documents::table
+        .filter(documents::parent_id.eq(&id))
+        .select(crate::models::Document::as_select())
+        .load(&mut conn)
+        .expect("Failed to load documents");
+
+    Json(document_views(&mut conn, docs))
+}
+
+/// The headers of a document that is a mail.
+async fn get_doc_mail(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<Json<Mail>, StatusCode> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    mails::table
+        .find(&id)
+        .select(Mail::as_select())
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load mail")
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Task updates, new and deleted documents and index commits as server-sent events.
+async fn stream_events(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    state.events.sse()
+}
+
+#[derive(Deserialize)]
+struct TaskParams {
+    /// Only list tasks in this state, e.g. `failed`.
+    status: Option<String>,
+}
+
+/// All ingestion tasks, newest first.
+async fn get_all_tasks(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<TaskParams>,
+) -> Json<Vec<Task>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let mut query = tasks::table
+        .select(Task::as_select())
+        .order(tasks::created_at.desc())
+        .into_boxed();
+
+    if let Some(status) = params.status {
+        query = query.filter(tasks::status.eq(status));
+    }
+
+    Json(query.load(&mut conn).expect("Failed to load tasks"))
+}
+
+async fn get_task(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<Json<Task>, StatusCode> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    tasks::table
+        .find(&id)
+        .select(Task::as_select())
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load task")
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn get_mail_accounts(State(state): State<Arc<AppState>>) -> Json<Vec<MailAccount>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    Json(
+        mail_accounts::table
+            .order(mail_accounts::id.asc())
+            .select(MailAccount::as_select())
+            .load(&mut conn)
+            .expect("Failed to load mail accounts"),
+    )
+}
+
+async fn create_mail_account(
+    State(state): State<Arc<AppState>>,
+    Json(account): Json<NewMailAccount>,
+) -> Result<(StatusCode, Json<MailAccount>), (StatusCode, String)> {
+    match account.security.as_deref() {
+        None | Some("ssl") | Some("none") => {}
+        Some(security) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unknown security `{}`, expected ssl or none", security),
+            ));
+        }
+    }
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let account = diesel::insert_into(mail_accounts::table)
+        .values(&account)
+        .returning(MailAccount::as_returning())
+        .get_result(&mut conn)
+        .expect("Failed to insert mail account");
+
+    Ok((StatusCode::CREATED, Json(account)))
+}
+
+/// Delete a mail account together with its rules.
+async fn delete_mail_account(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<i32>,
+) -> StatusCode {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let deleted = diesel::delete(mail_accounts::table.find(id))
+        .execute(&mut conn)
+        .expect("Failed to delete mail account");
+
+    if deleted == 0 {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::NO_CONTENT
+    }
+}
+
+async fn get_mail_rules(State(state): State<Arc<AppState>>) -> Json<Vec<MailRule>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    Json(
+        mail_rules::table
+            .order(mail_rules::id.asc())
+            .select(MailRule::as_select())
+            .load(&mut conn)
+            .expect("Failed to load mail rules"),
+    )
+}
+
+async fn create_mail_rule(
+    State(state): State<Arc<AppState>>,
+    Json(rule): Json<NewMailRule>,
+) -> Result<(StatusCode, Json<MailRule>), (StatusCode, String)> {
+    let bad_request = |message: String| Err((StatusCode::BAD_REQUEST, message));
+
+    match rule.scope.as_deref() {
+        None | Some("attachments") | Some("body") => {}
+        Some(scope) => {
+            return bad_request(format!(
+                "Unknown scope `{}`, expected attachments or body",
+                scope
+            ));
+        }
+    }
+
+    match rule.action.as_deref() {
+        None | Some("mark_read") | Some("flag") | Some("delete") => {}
+        Some("move") if rule.action_parameter.is_some() => {}
+        Some("move") => return bad_request("The `move` action needs a target folder".to_string()),
+        Some(action) => {
+            return bad_request(format!(
+                "Unknown action `{}`, expected mark_read, flag, move or delete",
+                action
+            ));
+        }
+    }
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let account = mail_accounts::table
+        .find(rule.account_id)
+        .select(mail_accounts::id)
+        .first::<i32>(&mut conn)
+        .optional()
+        .expect("Failed to load mail account");
+
+    if account.is_none() {
+        return bad_request(format!("There is no mail account {}", rule.account_id));
+    }
+
+    let rule = diesel::insert_into(mail_rules::table)
+        .values(&rule)
+        .returning(MailRule::as_returning())
+        .get_result(&mut conn)
+        .expect("Failed to insert mail rule");
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+async fn delete_mail_rule(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<i32>,
+) -> StatusCode {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let deleted = diesel::delete(mail_rules::table.find(id))
+        .execute(&mut conn)
+        .expect("Failed to delete mail rule");
+
+    if deleted == 0 {
+        StatusCode::NOT_FOUND
+    } else {
+        StatusCode::NO_CONTENT
+    }
+}
+
+/// Fetch mail now instead of waiting for the next scheduled run.
+async fn run_mail(State(state): State<Arc<AppState>>) -> StatusCode {
+    state.mail_notify.notify_one();
+    StatusCode::ACCEPTED
+}
+
+/// Answer a name that is taken already with `409 Conflict`.
+fn name_conflict(e: diesel::result::Error) -> (StatusCode, String) {
+    match e {
+        diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        ) => (
+            StatusCode::CONFLICT,
+            "The name is taken already".to_string(),
+        ),
+        e => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// A custom field and how many documents have a value for it.
+#[derive(Serialize)]
+struct CustomFieldView {
+    #[serde(flatten)]
+    field: CustomField,
+    document_count: i64,
+}
+
+async fn get_custom_fields(State(state): State<Arc<AppState>>) -> Json<Vec<CustomFieldView>> {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let counts: HashMap<i32, i64> = crate::schema::custom_field_values::table
+        .group_by(crate::schema::custom_field_values::field_id)
+        .select((
+            crate::schema::custom_field_values::field_id,
+            diesel::dsl::count_star(),
+        ))
+        .load::<(i32, i64)>(&mut conn)
+        .expect("Failed to count documents")
+        .into_iter()
+        .collect();
+
+    let all = custom_fields::load_all(&mut conn).expect("Failed to load custom fields");
+
+    Json(
+        all.into_iter()
+            .map(|field| CustomFieldView {
+                document_count: counts.get(&field.id).copied().unwrap_or(0),
+                field,
+            })
+            .collect(),
+    )
+}
+
+/// Only `select` fields have options, and they need at least one.
+fn validate_select_options(
+    data_type: custom_fields::DataType,
+    options: &[String],
+) -> Result<(), (StatusCode, String)> {
+    let is_select = data_type == custom_fields::DataType::Select;
+
+    if is_select && options.iter().all(|option| option.trim().is_empty()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "A select field needs options".to_string(),
+        ));
+    }
+
+    if !is_select && !options.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Only select fields have options".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+async fn create_custom_field(
+    State(state): State<Arc<AppState>>,
+    Json(mut field): Json<NewCustomField>,
+) -> Result<(StatusCode, Json<CustomField>), (StatusCode, String)> {
+    custom_fields::validate_name(&field.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let data_type: custom_fields::DataType = field
+        .data_type
+        .parse()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    validate_select_options(
+        data_type,
+        field.select_options.as_deref().unwrap_or_default(),
+    )?;
+
+    field.data_type = data_type.as_str().to_string();
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let field = diesel::insert_into(custom_fields_table::table)
+        .values(&field)
+        .returning(CustomField::as_returning())
+        .get_result(&mut conn)
+        .map_err(name_conflict)?;
+
+    Ok((StatusCode::CREATED, Json(field)))
+}
+
+/// A new name is reindexed for all documents with a value for the field. Values that are no
+/// longer among the options of a select field are kept.
+async fn update_custom_field(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<i32>,
+    Json(changes): Json<CustomFieldChanges>,
+) -> Result<Json<CustomField>, (StatusCode, String)> {
+    if let Some(name) = &changes.name {
+        custom_fields::validate_name(name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
+
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let field = custom_fields_table::table
+        .find(id)
+        .select(CustomField::as_select())
+        .first(&mut conn)
+        .optional()
+        .expect("Failed to load custom field")
+        .ok_or((StatusCode::NOT_FOUND, "No such custom field".to_string()))?;
+
+    if let Some(options) = &changes.select_options {
+        let data_type = field
+            .data_type
+            .parse()
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+        validate_select_options(data_type, options)?;
+    }
+
+    // nothing to change makes an empty UPDATE, which diesel refuses
+    if changes.name.is_none() && changes.select_options.is_none() {
+        return Ok(Json(field));
+    }
+
+    let field = diesel::update(custom_fields_table::table.find(id))
+        .set(&changes)
+        .returning(CustomField::as_returning())
+        .get_result(&mut conn)
+        .map_err(name_conflict)?;
+
+    if changes.name.is_some() {
+        let document_ids =
+            custom_fields::documents_with(&mut conn, id).expect("Failed to load documents");
+        reindex(&state, &mut conn, &document_ids).await;
+    }
+
+    Ok(Json(field))
+}
+
+/// Deletes the values of the field too.
+async fn delete_custom_field(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(id): axum::extract::Path<i32>,
+) -> StatusCode {
+    let mut conn = state.db_pool.get().expect("Failed to get db connection");
+
+    let document_ids =
+        custom_fields::documents_with(&mut conn, id).expect("Failed to load documents");
+
+    let deleted = diesel::delete(custom_fields_table::table.find(id))
+        .execute(&mut conn)
+        .expect("Failed to delete custom field");
+
+    if deleted == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+
+    reindex(&state, &mut conn, &document_ids).await;
+
+    StatusCode::NO_CONTENT
 }
 
 fn establish_connection() -> PgPool {
@@ -442,3 +1639,38 @@ fn establish_connection() -> PgPool {
         .expect("Failed to create pool");
     pool
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_page_image_widths() {
+        let cases = [
+            (0, 400),
+            (1, 400),
+            (400, 400),
+            (401, 800),
+            (1000, 1200),
+            (1200, 1200),
+            (1920, 2000),
+            (2000, 2000),
+            (10000, 2000),
+        ];
+
+        for (requested, expected) in cases {
+            assert_eq!(page_image_width(requested), expected, "{}", requested);
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_objects_to_a_file() {
+        let archive: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        let pdf = download_to_temp_file(ByteStream::from(archive.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(pdf.path()).unwrap(), archive);
+    }
+}
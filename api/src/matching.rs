@@ -0,0 +1,404 @@
+// Assigning tags, the correspondent and the type to new documents by what their text contains
+
+use std::str::FromStr;
+
+use diesel::{PgConnection, QueryDsl, QueryResult, RunQueryDsl, SelectableHelper};
+use regex::{Regex, RegexBuilder};
+
+use crate::models::{Correspondent, DocumentType, Tag};
+use crate::schema::{correspondents, document_types, tags};
+use crate::utils;
+
+/// How the `matching` text of a tag, correspondent or type is looked for in the text of a
+/// document, as in paperless-ngx.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Never assigned automatically.
+    None,
+    /// Any of the words, or of the phrases in double quotes.
+    Any,
+    /// All of the words, or of the phrases in double quotes.
+    All,
+    /// The text as it is.
+    Exact,
+    /// A regular expression.
+    Regex,
+    /// All of the words, each allowing a typo or two.
+    Fuzzy,
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Algorithm::None),
+            "any" => Ok(Algorithm::Any),
+            "all" => Ok(Algorithm::All),
+            "exact" => Ok(Algorithm::Exact),
+            "regex" => Ok(Algorithm::Regex),
+            "fuzzy" => Ok(Algorithm::Fuzzy),
+            other => Err(format!(
+                "Unknown matching algorithm `{}`, expected one of none, any, all, exact, regex, \
+                 fuzzy",
+                other
+            )),
+        }
+    }
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::None => "none",
+            Algorithm::Any => "any",
+            Algorithm::All => "all",
+            Algorithm::Exact => "exact",
+            Algorithm::Regex => "regex",
+            Algorithm::Fuzzy => "fuzzy",
+        }
+    }
+}
+
+/// Check the algorithm and, for `regex`, the pattern of a new or changed rule, returning the
+/// algorithm as it is stored.
+pub fn validate(algorithm: &str, pattern: Option<&str>) -> Result<String, String> {
+    let algorithm: Algorithm = algorithm.parse()?;
+
+    if algorithm == Algorithm::Regex
+        && let Some(pattern) = pattern
+    {
+        Regex::new(pattern).map_err(|e| format!("Invalid regular expression: {}", e))?;
+    }
+
+    Ok(algorithm.as_str().to_string())
+}
+
+/// The words and quoted phrases of a pattern, e.g. `"acme corp" invoice`.
+fn terms(pattern: &str) -> Vec<String> {
+    pattern
+        .split('"')
+        .enumerate()
+        .flat_map(|(index, part)| {
+            // every other part is inside quotes
+            if index % 2 == 1 {
+                vec![part.split_whitespace().collect::<Vec<&str>>().join(" ")]
+            } else {
+                part.split_whitespace().map(str::to_string).collect()
+            }
+        })
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// A word or phrase as a whole, however much whitespace is between the words of a phrase. Word
+/// boundaries only go next to word characters, `\bC++\b` would never match `C++ developer`.
+fn term_regex(term: &str, insensitive: bool) -> Option<Regex> {
+    let words: Vec<String> = term.split_whitespace().map(regex::escape).collect();
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+
+    let start = if is_word(term.chars().next()) {
+        r"\b"
+    } else {
+        ""
+    };
+    let end = if is_word(term.chars().next_back()) {
+        r"\b"
+    } else {
+        ""
+    };
+
+    RegexBuilder::new(&format!("{}{}{}", start, words.join(r"\s+"), end))
+        .case_insensitive(insensitive)
+        .build()
+        .ok()
+}
+
+/// Levenshtein distance, by characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Whether the text of a document matches the pattern of a rule. An empty pattern matches
+/// nothing, and neither does an invalid regular expression.
+pub fn matches(algorithm: Algorithm, pattern: &str, insensitive: bool, text: &str) -> bool {
+    if pattern.trim().is_empty() {
+        return false;
+    }
+
+    match algorithm {
+        Algorithm::None => false,
+        Algorithm::Any => terms(pattern)
+            .iter()
+            .any(|term| term_regex(term, insensitive).is_some_and(|re| re.is_match(text))),
+        Algorithm::All => terms(pattern)
+            .iter()
+            .all(|term| term_regex(term, insensitive).is_some_and(|re| re.is_match(text))),
+        Algorithm::Exact => RegexBuilder::new(&regex::escape(pattern.trim()))
+            .case_insensitive(insensitive)
+            .build()
+            .is_ok_and(|re| re.is_match(text)),
+        Algorithm::Regex => RegexBuilder::new(pattern)
+            .case_insensitive(insensitive)
+            .build()
+            .is_ok_and(|re| re.is_match(text)),
+        Algorithm::Fuzzy => {
+            let normalize = |word: &str| {
+                if insensitive {
+                    word.to_lowercase()
+                } else {
+                    word.to_string()
+                }
+            };
+
+            let words: Vec<String> = text
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(normalize)
+                .collect();
+
+            pattern.split_whitespace().map(normalize).all(|wanted| {
+                let distance = utils::fuzzy_distance(&wanted) as usize;
+                words
+                    .iter()
+                    .any(|word| edit_distance(&wanted, word) <= distance)
+            })
+        }
+    }
+}
+
+/// Rules are stored with their algorithm as text, one that does not parse never matches.
+fn rule_matches(pattern: &str, algorithm: &str, insensitive: bool, text: &str) -> bool {
+    algorithm
+        .parse()
+        .is_ok_and(|algorithm| matches(algorithm, pattern, insensitive, text))
+}
+
+/// Every tag whose rule matches the text of a document.
+pub fn tags_for(conn: &mut PgConnection, text: &str) -> QueryResult<Vec<i32>> {
+    let candidates: Vec<Tag> = tags::table
+        .select(Tag::as_select())
+        .order(tags::id)
+        .load(conn)?;
+
+    Ok(candidates
+        .into_iter()
+        .filter(|tag| {
+            rule_matches(
+                &tag.matching,
+                &tag.matching_algorithm,
+                tag.is_insensitive,
+                text,
+            )
+        })
+        .map(|tag| tag.id)
+        .collect())
+}
+
+/// The first correspondent, oldest first, whose rule matches the text of a document.
+pub fn correspondent_for(conn: &mut PgConnection, text: &str) -> QueryResult<Option<i32>> {
+    let candidates: Vec<Correspondent> = correspondents::table
+        .select(Correspondent::as_select())
+        .order(correspondents::id)
+        .load(conn)?;
+
+    Ok(candidates
+        .into_iter()
+        .find(|correspondent| {
+            rule_matches(
+                &correspondent.matching,
+                &correspondent.matching_algorithm,
+                correspondent.is_insensitive,
+                text,
+            )
+        })
+        .map(|correspondent| correspondent.id))
+}
+
+/// The first document type, oldest first, whose rule matches the text of a document.
+pub fn document_type_for(conn: &mut PgConnection, text: &str) -> QueryResult<Option<i32>> {
+    let candidates: Vec<DocumentType> = document_types::table
+        .select(DocumentType::as_select())
+        .order(document_types::id)
+        .load(conn)?;
+
+    Ok(candidates
+        .into_iter()
+        .find(|document_type| {
+            rule_matches(
+                &document_type.matching,
+                &document_type.matching_algorithm,
+                document_type.is_insensitive,
+                text,
+            )
+        })
+        .map(|document_type| document_type.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "Invoice No. 42 from ACME Corp.\nSenior C++ developer, 1 x €100 per hour";
+
+    #[test]
+    fn splits_patterns_into_words_and_phrases() {
+        assert_eq!(
+            terms(r#"invoice "acme   corp" total"#),
+            vec!["invoice", "acme corp", "total"]
+        );
+        assert_eq!(terms(r#""" "unclosed phrase"#), vec!["unclosed phrase"]);
+    }
+
+    #[test]
+    fn matches_words_and_phrases_as_a_whole() {
+        let cases = [
+            // pattern, algorithm, case insensitive, expected
+            ("invoice", Algorithm::Any, true, true),
+            ("invoice", Algorithm::Any, false, false),
+            ("Invoice", Algorithm::Any, false, true),
+            ("voice", Algorithm::Any, true, false),
+            ("receipt invoice", Algorithm::Any, true, true),
+            ("receipt voucher", Algorithm::Any, true, false),
+            (r#""acme corp""#, Algorithm::Any, true, true),
+            (r#""acme corp""#, Algorithm::Any, false, false),
+            (r#""from ACME""#, Algorithm::Any, false, true),
+            ("invoice acme", Algorithm::All, true, true),
+            ("invoice acme", Algorithm::All, false, false),
+            ("invoice receipt", Algorithm::All, true, false),
+            (r#"invoice "42 from acme""#, Algorithm::All, true, true),
+            (r#""acme invoice""#, Algorithm::All, true, false),
+            // terms that start or end with something other than a letter or digit
+            ("C++", Algorithm::Any, false, true),
+            ("c++", Algorithm::Any, false, false),
+            ("c++", Algorithm::Any, true, true),
+            ("€100", Algorithm::Any, true, true),
+            ("€10", Algorithm::Any, true, false),
+            ("Corp.", Algorithm::All, true, true),
+            ("C", Algorithm::Any, false, true),
+            ("Cor", Algorithm::Any, false, false),
+        ];
+
+        for (pattern, algorithm, insensitive, expected) in cases {
+            assert_eq!(
+                matches(algorithm, pattern, insensitive, TEXT),
+                expected,
+                "{:?} {} (insensitive: {})",
+                algorithm,
+                pattern,
+                insensitive
+            );
+        }
+    }
+
+    #[test]
+    fn matches_exact_text_and_expressions() {
+        let cases = [
+            ("no. 42 from", Algorithm::Exact, true, true),
+            ("No. 42 from", Algorithm::Exact, false, true),
+            ("no. 42 from", Algorithm::Exact, false, false),
+            ("No. 42  from", Algorithm::Exact, false, false),
+            // the text may be anywhere, even inside a word
+            ("nvoi", Algorithm::Exact, false, true),
+            (r"no\. \d+", Algorithm::Regex, true, true),
+            (r"no\. \d+", Algorithm::Regex, false, false),
+            (r"^Senior", Algorithm::Regex, false, false),
+            (r"(?m)^Senior", Algorithm::Regex, false, true),
+            // an invalid expression matches nothing
+            (r"(unclosed", Algorithm::Regex, true, false),
+        ];
+
+        for (pattern, algorithm, insensitive, expected) in cases {
+            assert_eq!(
+                matches(algorithm, pattern, insensitive, TEXT),
+                expected,
+                "{:?} {} (insensitive: {})",
+                algorithm,
+                pattern,
+                insensitive
+            );
+        }
+    }
+
+    #[test]
+    fn matches_words_with_typos() {
+        let cases = [
+            ("invoice", true, true),
+            ("invoise", true, true),
+            ("inovice", true, true),
+            ("INVOICE", false, false),
+            ("INVOICE", true, true),
+            ("Invoise", false, true),
+            ("acme devloper", true, true),
+            ("acme designer", true, false),
+            // short words allow a single typo
+            ("acne", true, true),
+            ("amce", true, false),
+            ("42", true, true),
+            ("4242", true, false),
+        ];
+
+        for (pattern, insensitive, expected) in cases {
+            assert_eq!(
+                matches(Algorithm::Fuzzy, pattern, insensitive, TEXT),
+                expected,
+                "{} (insensitive: {})",
+                pattern,
+                insensitive
+            );
+        }
+    }
+
+    #[test]
+    fn matches_nothing_without_a_pattern() {
+        for algorithm in [
+            Algorithm::None,
+            Algorithm::Any,
+            Algorithm::All,
+            Algorithm::Exact,
+            Algorithm::Regex,
+            Algorithm::Fuzzy,
+        ] {
+            assert!(!matches(algorithm, "  ", true, TEXT), "{:?}", algorithm);
+        }
+
+        assert!(!matches(Algorithm::None, "invoice", true, TEXT));
+        assert!(!rule_matches("invoice", "sometimes", true, TEXT));
+        assert!(rule_matches("invoice", "any", true, TEXT));
+    }
+
+    #[test]
+    fn counts_edits_by_character() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("flaw", "lawn"), 2);
+        assert_eq!(edit_distance("größe", "grösse"), 2);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn validates_algorithms_and_expressions() {
+        assert_eq!(validate(" Fuzzy ", None), Ok("fuzzy".to_string()));
+        assert_eq!(validate("regex", Some(r"\d+")), Ok("regex".to_string()));
+        assert!(validate("regex", Some("(unclosed")).is_err());
+        // only regular expressions are checked as such
+        assert!(validate("exact", Some("(unclosed")).is_ok());
+        assert!(validate("sometimes", None).is_err());
+    }
+}
@@ -1,12 +1,407 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Insertable, Queryable, Selectable, Serialize, Deserialize)]
+use crate::parsers::THUMBNAIL_WIDTH;
+
+#[derive(Clone, AsChangeset, Insertable, Queryable, Selectable, Serialize, Deserialize)]
 #[diesel(table_name = crate::schema::documents)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(treat_none_as_null = true)]
 pub struct Document {
     pub id: String,
     pub title: String,
     pub body: String,
     pub thumbnail_url: String,
+    /// SHA-256 of the original upload, hex encoded.
+    pub checksum: Option<String>,
+    /// MIME type of the original upload, which may be an image rather than a PDF.
+    pub mime_type: String,
+    /// File extension of the original upload, without the dot.
+    pub extension: String,
+    /// The document this one came with, e.g. the mail it was attached to.
+    pub parent_id: Option<String>,
+    /// The date the document was written, as found in its text.
+    pub created: Option<NaiveDate>,
+    /// When the document was uploaded.
+    pub added: NaiveDateTime,
+    /// Who the document is from, or to.
+    pub correspondent_id: Option<i32>,
+    /// What kind of document it is, e.g. an invoice.
+    pub document_type_id: Option<i32>,
+}
+
+/// The headers of a document that is an e-mail.
+#[derive(Clone, Insertable, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::mails)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Mail {
+    pub document_id: String,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub subject: Option<String>,
+    pub sent_at: Option<NaiveDateTime>,
+    pub message_id: Option<String>,
+}
+
+/// The text of one page of a document, numbered from 1 like the pages of its archive.
+#[derive(Clone, Insertable, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::document_pages)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DocumentPage {
+    pub document_id: String,
+    pub page_number: i32,
+    pub body: String,
+}
+
+impl Document {
+    /// S3 key of the original upload as it was received.
+    pub fn original_key(&self) -> String {
+        format!("{}/document.{}", self.id, self.extension)
+    }
+
+    /// S3 key of a page of the archive rendered as WebP, counted from 1.
+    pub fn page_image_key(&self, page: u16, width: u16) -> String {
+        format!("{}/pages/{}-{}.webp", self.id, page, width)
+    }
+
+    /// Where the API serves the thumbnail, the first page at [`THUMBNAIL_WIDTH`].
+    pub fn thumbnail_path(&self) -> String {
+        format!(
+            "/api/docs/{}/pages/1/image?width={}",
+            self.id, THUMBNAIL_WIDTH
+        )
+    }
+}
+
+/// Lifecycle of an ingestion task, stored as text in `tasks.status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    Queued,
+    Extracting,
+    Ocr,
+    Thumbnailing,
+    Indexing,
+    Done,
+    Failed,
+}
+
+impl TaskStatus {
+    /// The stages a task goes through while the worker is processing it.
+    pub const IN_PROGRESS: [TaskStatus; 4] = [
+        TaskStatus::Extracting,
+        TaskStatus::Ocr,
+        TaskStatus::Thumbnailing,
+        TaskStatus::Indexing,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskStatus::Queued => "queued",
+            TaskStatus::Extracting => "extracting",
+            TaskStatus::Ocr => "ocr",
+            TaskStatus::Thumbnailing => "thumbnailing",
+            TaskStatus::Indexing => "indexing",
+            TaskStatus::Done => "done",
+            TaskStatus::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Clone, Queryable, QueryableByName, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::tasks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Task {
+    pub id: String,
+    pub document_id: String,
+    pub filename: String,
+    /// Where the upload is staged on the server until it has been ingested.
+    #[serde(skip_serializing)]
+    pub file_path: String,
+    pub ocr_mode: String,
+    pub ocr_languages: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub started_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub checksum: Option<String>,
+    /// Names of the tags to assign once ingested.
+    pub tags: Vec<String>,
+    /// The document to link the ingested document to as its child.
+    pub parent_id: Option<String>,
+    /// Password of an encrypted PDF, cleared once the task is finished.
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::schema::tasks)]
+pub struct NewTask {
+    pub id: String,
+    pub document_id: String,
+    pub filename: String,
+    pub file_path: String,
+    pub ocr_mode: String,
+    pub ocr_languages: String,
+    pub status: String,
+    pub checksum: Option<String>,
+    pub finished_at: Option<NaiveDateTime>,
+    pub tags: Vec<String>,
+    pub parent_id: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::mail_accounts)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MailAccount {
+    pub id: i32,
+    pub name: String,
+    pub host: String,
+    pub port: i32,
+    /// `ssl` for IMAPS or `none` for plain IMAP.
+    pub security: String,
+    pub username: String,
+    /// Stored as it is, or `env:NAME` to read it from an environment variable.
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::mail_accounts)]
+pub struct NewMailAccount {
+    pub name: String,
+    pub host: String,
+    pub port: Option<i32>,
+    pub security: Option<String>,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::mail_rules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MailRule {
+    pub id: i32,
+    pub account_id: i32,
+    pub name: String,
+    pub folder: String,
+    pub filter_from: Option<String>,
+    pub filter_subject: Option<String>,
+    pub max_age_days: Option<i32>,
+    /// `attachments` to ingest PDF attachments or `body` to ingest the mail text.
+    pub scope: String,
+    /// `mark_read`, `flag`, `move` or `delete`, applied once a mail is ingested.
+    pub action: String,
+    /// The target folder of `move`.
+    pub action_parameter: Option<String>,
+    /// Names of the tags to assign to the ingested documents.
+    pub tags: Vec<String>,
+    pub enabled: bool,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::mail_rules)]
+pub struct NewMailRule {
+    pub account_id: i32,
+    pub name: String,
+    pub folder: Option<String>,
+    pub filter_from: Option<String>,
+    pub filter_subject: Option<String>,
+    pub max_age_days: Option<i32>,
+    pub scope: Option<String>,
+    pub action: Option<String>,
+    pub action_parameter: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+/// A tag, nested under its parent if it has one, e.g. `Tax` under `Finance`.
+#[derive(Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    /// Hex color, e.g. `#a6cee3`.
+    pub color: String,
+    pub parent_id: Option<i32>,
+    /// Assigns the tag to new documents whose text matches, see [`crate::matching`].
+    pub matching: String,
+    pub matching_algorithm: String,
+    pub is_insensitive: bool,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::tags)]
+pub struct NewTag {
+    pub name: String,
+    pub color: Option<String>,
+    pub parent_id: Option<i32>,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+/// The fields of a tag to change, all others stay as they are.
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = crate::schema::tags)]
+pub struct TagChanges {
+    pub name: Option<String>,
+    pub color: Option<String>,
+    /// `null` moves the tag to the top level.
+    #[serde(default, deserialize_with = "nullable")]
+    pub parent_id: Option<Option<i32>>,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+/// Who a document is from (or to), e.g. a bank or an employer.
+#[derive(Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::correspondents)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Correspondent {
+    pub id: i32,
+    pub name: String,
+    /// Assigns new documents whose text matches to this correspondent, see
+    /// [`crate::matching`].
+    pub matching: String,
+    pub matching_algorithm: String,
+    pub is_insensitive: bool,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::correspondents)]
+pub struct NewCorrespondent {
+    pub name: String,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = crate::schema::correspondents)]
+pub struct CorrespondentChanges {
+    pub name: Option<String>,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+/// A kind of document, e.g. an invoice, a contract or a payslip.
+#[derive(Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::document_types)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DocumentType {
+    pub id: i32,
+    pub name: String,
+    /// Assigns new documents whose text matches to this type, see [`crate::matching`].
+    pub matching: String,
+    pub matching_algorithm: String,
+    pub is_insensitive: bool,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::document_types)]
+pub struct NewDocumentType {
+    pub name: String,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = crate::schema::document_types)]
+pub struct DocumentTypeChanges {
+    pub name: Option<String>,
+    pub matching: Option<String>,
+    pub matching_algorithm: Option<String>,
+    pub is_insensitive: Option<bool>,
+}
+
+/// A field defined by the user, e.g. the amount of an invoice, that documents can have a value
+/// for.
+#[derive(Clone, Queryable, Selectable, Serialize)]
+#[diesel(table_name = crate::schema::custom_fields)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CustomField {
+    pub id: i32,
+    /// Also the name to search it by, e.g. `amount:>100`.
+    pub name: String,
+    /// One of `string`, `integer`, `monetary`, `date`, `boolean`, `url`, `select` or
+    /// `document_link`.
+    pub data_type: String,
+    /// The values to choose from for `select`.
+    pub select_options: Vec<String>,
+}
+
+#[derive(Insertable, Deserialize)]
+#[diesel(table_name = crate::schema::custom_fields)]
+pub struct NewCustomField {
+    pub name: String,
+    pub data_type: String,
+    pub select_options: Option<Vec<String>>,
+}
+
+/// The data type of a field cannot be changed, it would make its values invalid.
+#[derive(AsChangeset, Deserialize)]
+#[diesel(table_name = crate::schema::custom_fields)]
+pub struct CustomFieldChanges {
+    pub name: Option<String>,
+    pub select_options: Option<Vec<String>>,
+}
+
+/// The value of a custom field for a document, in the column for the data type of the field.
+/// Amounts of money are in cents, with the currency in `value_text` if one was given.
+#[derive(Clone, Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::custom_field_values)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CustomFieldValue {
+    pub document_id: String,
+    pub field_id: i32,
+    pub value_text: Option<String>,
+    pub value_integer: Option<i64>,
+    pub value_monetary: Option<i64>,
+    pub value_date: Option<NaiveDate>,
+    pub value_boolean: Option<bool>,
+    pub value_document_id: Option<String>,
+}
+
+/// A custom field value as the API takes and returns it, e.g.
+/// `{"field": 1, "value": "EUR12.50"}`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FieldValue {
+    pub field: i32,
+    pub value: serde_json::Value,
+}
+
+#[derive(Insertable, Queryable, Selectable)]
+#[diesel(table_name = crate::schema::document_tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DocumentTag {
+    pub document_id: String,
+    pub tag_id: i32,
+}
+
+/// A document as the API returns it, with what is assigned to it.
+#[derive(Serialize)]
+pub struct DocumentView {
+    #[serde(flatten)]
+    pub document: Document,
+    /// Ids of its tags.
+    pub tags: Vec<i32>,
+    pub custom_fields: Vec<FieldValue>,
+}
+
+/// Tell a field set to `null` (`Some(None)`) from a missing one (`None`).
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
 }
@@ -0,0 +1,228 @@
+// OCR fallback for pages that have no text layer (e.g. scanned paper mail)
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use pdfium_render::prelude::PdfRenderConfig;
+use rusty_tesseract::{Args, Image};
+use tokio::process::Command;
+
+use crate::utils;
+
+/// Roughly A4 at 300 DPI, which is what Tesseract is tuned for.
+const OCR_RENDER_WIDTH: i32 = 2480;
+const OCR_DPI: i32 = 300;
+
+pub type OcrResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Which pages get OCR'd, mirroring paperless-ngx's `PAPERLESS_OCR_MODE`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OcrMode {
+    /// Only OCR pages that have no text layer.
+    #[default]
+    Skip,
+    /// OCR every page and replace whatever text layer it had.
+    Redo,
+    /// Rasterise every page and OCR it, ignoring the text layer entirely.
+    Force,
+}
+
+impl FromStr for OcrMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "skip" => Ok(OcrMode::Skip),
+            "redo" => Ok(OcrMode::Redo),
+            "force" => Ok(OcrMode::Force),
+            other => Err(format!(
+                "Unknown OCR mode `{}`, expected one of skip, redo, force",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OcrMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OcrMode::Skip => write!(f, "skip"),
+            OcrMode::Redo => write!(f, "redo"),
+            OcrMode::Force => write!(f, "force"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OcrConfig {
+    pub mode: OcrMode,
+    /// Tesseract languages joined with `+`, e.g. `eng+deu+nld`.
+    pub languages: String,
+}
+
+impl Default for OcrConfig {
+    fn default() -> Self {
+        OcrConfig {
+            mode: OcrMode::default(),
+            languages: "eng".to_string(),
+        }
+    }
+}
+
+impl OcrConfig {
+    /// Read the global defaults from `OCR_MODE` and `OCR_LANGUAGES`.
+    pub fn from_env() -> Self {
+        let mut config = OcrConfig::default();
+
+        if let Ok(mode) = env::var("OCR_MODE") {
+            match mode.parse() {
+                Ok(mode) => config.mode = mode,
+                Err(e) => println!("Ignoring OCR_MODE: {}", e),
+            }
+        }
+
+        if let Ok(languages) = env::var("OCR_LANGUAGES") {
+            match parse_languages(&languages) {
+                Ok(languages) => config.languages = languages,
+                Err(e) => println!("Ignoring OCR_LANGUAGES: {}", e),
+            }
+        }
+
+        config
+    }
+}
+
+/// Validate a `+`-separated list of Tesseract language codes, e.g. `eng+deu+nld`.
+pub fn parse_languages(input: &str) -> Result<String, String> {
+    let languages: Vec<&str> = input.trim().split('+').map(str::trim).collect();
+
+    let valid = languages.iter().all(|lang| {
+        !lang.is_empty() && lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    });
+
+    if !valid {
+        return Err(format!(
+            "Invalid OCR languages `{}`, expected codes joined with `+`, e.g. eng+deu",
+            input
+        ));
+    }
+
+    Ok(languages.join("+"))
+}
+
+/// The text layer of a PDF page by page, as far as the configured [`OcrMode`] trusts it.
+pub async fn text_layer(path: &Path, config: &OcrConfig) -> Vec<String> {
+    match config.mode {
+        OcrMode::Skip | OcrMode::Redo => utils::pdf_to_pages(path).await,
+        OcrMode::Force => Vec::new(),
+    }
+}
+
+/// OCR the pages selected by the configured [`OcrMode`], returning the text of every page.
+/// Falls back to the text layer alone if OCR fails.
+///
+/// OCR is CPU-bound and blocks, so this should only be called on the ingestion worker.
+pub fn ocr_text(path: &Path, pages: Vec<String>, config: &OcrConfig) -> Vec<String> {
+    match ocr_pages(path, pages.clone(), config, None) {
+        Ok(pages) => pages,
+        Err(e) => {
+            println!("OCR failed, falling back to the text layer: {}", e);
+            pages
+        }
+    }
+}
+
+/// Run Tesseract over the pages of `pages` selected by `config.mode`, rendering each one with
+/// pdfium. Pages missing from `pages` altogether are treated as having no text layer. With
+/// [`OcrMode::Redo`] a page keeps its text layer if OCR finds nothing on it.
+///
+/// Not async so should be run on the ingestion worker or a blocking thread pool
+pub fn ocr_pages(
+    path: &Path,
+    mut pages: Vec<String>,
+    config: &OcrConfig,
+    password: Option<&str>,
+) -> OcrResult<Vec<String>> {
+    let pdfium = utils::bind_pdfium();
+    let document = pdfium.load_pdf_from_file(path, password)?;
+
+    let page_count = document.pages().len() as usize;
+    pages.resize(page_count.max(pages.len()), String::new());
+
+    let needs_ocr = |text: &str| config.mode != OcrMode::Skip || text.trim().is_empty();
+
+    if !pages.iter().any(|page| needs_ocr(page)) {
+        return Ok(pages);
+    }
+
+    let render_config = PdfRenderConfig::new().set_target_width(OCR_RENDER_WIDTH);
+
+    let args = Args {
+        lang: config.languages.clone(),
+        dpi: Some(OCR_DPI),
+        ..Args::default()
+    };
+
+    for (index, page) in document.pages().iter().enumerate() {
+        if !needs_ocr(&pages[index]) {
+            continue;
+        }
+
+        let bitmap = page.render_with_config(&render_config)?.as_image();
+        let image = Image::from_dynamic_image(&bitmap)?;
+
+        let text = rusty_tesseract::image_to_string(&image, &args)?;
+
+        if !text.trim().is_empty() || config.mode != OcrMode::Redo {
+            pages[index] = text;
+        }
+
+        println!(
+            "OCR'd page {} of {} ({}, {})",
+            index + 1,
+            page_count,
+            config.mode,
+            config.languages
+        );
+    }
+
+    Ok(pages)
+}
+
+/// Produce a searchable PDF/A copy of the PDF at `path` with an embedded OCR text layer by
+/// shelling out to `ocrmypdf`, honouring the configured mode and languages.
+pub async fn create_archive(path: &Path, output: &Path, config: &OcrConfig) -> OcrResult<()> {
+    let mode_flag = match config.mode {
+        OcrMode::Skip => "--skip-text",
+        OcrMode::Redo => "--redo-ocr",
+        OcrMode::Force => "--force-ocr",
+    };
+
+    let result = Command::new("ocrmypdf")
+        .args([
+            "--quiet",
+            "--output-type",
+            "pdfa",
+            mode_flag,
+            "-l",
+            config.languages.as_str(),
+        ])
+        .arg(path)
+        .arg(output)
+        .output()
+        .await?;
+
+    if !result.status.success() {
+        return Err(format!(
+            "ocrmypdf exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(())
+}
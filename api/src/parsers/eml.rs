@@ -0,0 +1,160 @@
+// Mails exported as `.eml`: the headers and body are indexed, the mail is rendered to a PDF and
+// its PDF attachments become documents of their own
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use mail_parser::{Message, MessageParser, MessagePart, MimeHeaders};
+use tempfile::NamedTempFile;
+
+use super::{
+    DocumentParser, MailHeaders, Metadata, ParseResult, Source, copy_archive, render_text,
+};
+use crate::filetype::FileType;
+use crate::utils;
+
+const EML: FileType = FileType {
+    mime_type: "message/rfc822",
+    extensions: &["eml"],
+};
+
+pub struct EmlParser;
+
+#[async_trait(?Send)]
+impl DocumentParser for EmlParser {
+    /// Mail bodies can be in any charset, so this goes by the headers alone.
+    fn sniff(&self, header: &[u8], _filename: &str) -> Option<FileType> {
+        looks_like_mail(header).then_some(EML)
+    }
+
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>> {
+        let bytes = std::fs::read(source.path)?;
+        let text = mail_text(&parse(&bytes)?);
+
+        Ok(Some(render_text(&text)?))
+    }
+
+    async fn extract_text(&self, source: &Source, _pdf: &Path) -> ParseResult<Vec<String>> {
+        let bytes = std::fs::read(source.path)?;
+        Ok(utils::text_pages(&mail_text(&parse(&bytes)?)))
+    }
+
+    async fn archive(&self, _source: &Source, pdf: &Path, output: &Path) -> ParseResult<()> {
+        copy_archive(pdf, output).await
+    }
+
+    fn metadata(&self, source: &Source) -> ParseResult<Metadata> {
+        let bytes = std::fs::read(source.path)?;
+        let message = parse(&bytes)?;
+
+        let header = |name: &str| {
+            message
+                .header_raw(name)
+                .map(|value| value.trim().to_string())
+        };
+
+        let attachments = message
+            .attachments()
+            .filter(|part| is_pdf_attachment(part))
+            .map(|part| {
+                let name = part
+                    .attachment_name()
+                    .unwrap_or("attachment.pdf")
+                    .to_string();
+                (name, part.contents().to_vec())
+            })
+            .collect();
+
+        let subject = message.subject().map(str::to_string);
+
+        Ok(Metadata {
+            title: subject.clone(),
+            mail: Some(MailHeaders {
+                from: header("From"),
+                to: header("To"),
+                subject,
+                date: message
+                    .date()
+                    .and_then(|date| chrono::DateTime::from_timestamp(date.to_timestamp(), 0))
+                    .map(|date| date.naive_utc()),
+                message_id: message.message_id().map(str::to_string),
+            }),
+            attachments,
+        })
+    }
+}
+
+fn parse(bytes: &[u8]) -> ParseResult<Message<'_>> {
+    MessageParser::default()
+        .parse(bytes)
+        .ok_or_else(|| "Failed to parse the mail".into())
+}
+
+/// The headers a reader cares about followed by the plain text body.
+pub fn mail_text(message: &Message) -> String {
+    let mut text = String::new();
+
+    for header in ["From", "To", "Date", "Subject"] {
+        if let Some(value) = message.header_raw(header) {
+            text.push_str(&format!("{}: {}\n", header, value.trim()));
+        }
+    }
+
+    text.push('\n');
+
+    if let Some(body) = message.body_text(0) {
+        text.push_str(&body);
+    }
+
+    text
+}
+
+pub fn is_pdf_attachment(part: &MessagePart) -> bool {
+    let by_type = part.content_type().is_some_and(|content_type| {
+        content_type.ctype().eq_ignore_ascii_case("application")
+            && content_type
+                .subtype()
+                .is_some_and(|subtype| subtype.eq_ignore_ascii_case("pdf"))
+    });
+
+    // plenty of mail clients send PDFs as application/octet-stream
+    let by_name = part
+        .attachment_name()
+        .is_some_and(|name| name.to_lowercase().ends_with(".pdf"));
+
+    by_type || by_name
+}
+
+/// A header block (`Name: value` lines, possibly folded) that has a sender and at least one
+/// other header only mails have.
+fn looks_like_mail(header: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(header);
+    let mut names = Vec::new();
+
+    for line in text.lines() {
+        if line.is_empty() {
+            break; // end of the headers
+        }
+
+        if line.starts_with([' ', '\t']) {
+            continue; // folded header
+        }
+
+        let Some((name, _)) = line.split_once(':') else {
+            return false;
+        };
+
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+            return false;
+        }
+
+        names.push(name.to_lowercase());
+    }
+
+    let has = |name: &str| names.iter().any(|n| n == name);
+
+    has("from")
+        && ["date", "subject", "message-id", "mime-version", "received"]
+            .iter()
+            .any(|name| has(name))
+}
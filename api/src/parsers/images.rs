@@ -0,0 +1,106 @@
+// Photos and scans, wrapped in a PDF before OCR
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use tempfile::NamedTempFile;
+use tokio::process::Command;
+
+use super::{DocumentParser, ParseResult, Source};
+use crate::filetype::FileType;
+
+const JPEG: FileType = FileType {
+    mime_type: "image/jpeg",
+    extensions: &["jpg", "jpeg"],
+};
+
+const PNG: FileType = FileType {
+    mime_type: "image/png",
+    extensions: &["png"],
+};
+
+/// Possibly with several pages, as scanners like to produce.
+const TIFF: FileType = FileType {
+    mime_type: "image/tiff",
+    extensions: &["tif", "tiff"],
+};
+
+pub struct ImageParser;
+
+#[async_trait(?Send)]
+impl DocumentParser for ImageParser {
+    fn sniff(&self, header: &[u8], _filename: &str) -> Option<FileType> {
+        if header.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(JPEG)
+        } else if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(PNG)
+        } else if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+            Some(TIFF)
+        } else {
+            None
+        }
+    }
+
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>> {
+        Ok(Some(image_to_pdf(source.path, source.file_type).await?))
+    }
+}
+
+/// Wrap an image in a PDF, one page per image (or per frame of a multi-page TIFF), with
+/// [`img2pdf`](https://gitlab.mister-muffin.de/josch/img2pdf), which ships with `ocrmypdf`.
+/// JPEGs are embedded as they are, without re-encoding.
+async fn image_to_pdf(path: &Path, file_type: FileType) -> ParseResult<NamedTempFile> {
+    let output = NamedTempFile::new()?;
+
+    // img2pdf refuses images with an alpha channel, e.g. screenshots, so flatten those first
+    let flattened = if file_type == PNG {
+        flatten_alpha(path)?
+    } else {
+        None
+    };
+
+    let input = flattened.as_ref().map(|file| file.path()).unwrap_or(path);
+
+    let result = Command::new("img2pdf")
+        .arg("--rotation=ifvalid") // phones store the orientation in EXIF
+        .args(["--pagesize", "A4", "--fit", "shrink"])
+        .arg("--output")
+        .arg(output.path())
+        .arg(input)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run img2pdf: {}", e))?;
+
+    if !result.status.success() {
+        return Err(format!(
+            "img2pdf failed with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(output)
+}
+
+/// A copy of the PNG without its alpha channel, or `None` if it does not have one.
+fn flatten_alpha(path: &Path) -> ParseResult<Option<NamedTempFile>> {
+    let image = image::open(path)?;
+
+    if !image.color().has_alpha() {
+        return Ok(None);
+    }
+
+    // on white paper, rather than whatever colour the transparent pixels happen to have
+    let rgba = image.into_rgba8();
+    let rgb = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u16 * a as u16 + 255 * (255 - a as u16)) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    });
+
+    let flattened = NamedTempFile::with_suffix(".png")?;
+    rgb.save_with_format(flattened.path(), image::ImageFormat::Png)?;
+
+    Ok(Some(flattened))
+}
@@ -0,0 +1,228 @@
+// Parsers turn an upload of a particular type into what ingestion needs: its text, a PDF
+// archive, a thumbnail and any metadata. Support for a new format is a new module here plus a
+// line in `ParserRegistry::default`, each parser recognises the files it handles itself.
+
+use std::error::Error;
+use std::path::Path;
+
+use async_trait::async_trait;
+use image::RgbImage;
+use tempfile::NamedTempFile;
+
+use crate::filetype::{self, FileType};
+use crate::models::TaskStatus;
+use crate::ocr::{self, OcrConfig};
+use crate::utils;
+
+mod eml;
+mod images;
+#[cfg(feature = "office")]
+mod office;
+mod pdf;
+mod text;
+
+pub use eml::{is_pdf_attachment, mail_text};
+
+/// Width of the thumbnails shown in the document grid, in pixels.
+pub const THUMBNAIL_WIDTH: u16 = 400;
+
+pub type ParseResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The upload being ingested.
+pub struct Source<'a> {
+    /// Where the upload is staged.
+    pub path: &'a Path,
+    /// As sniffed by the parser.
+    pub file_type: FileType,
+    pub ocr_config: &'a OcrConfig,
+    /// To try on encrypted files, in order.
+    pub passwords: &'a [String],
+    /// Report that ingestion moved on to another stage.
+    pub progress: &'a dyn Fn(TaskStatus),
+}
+
+/// Headers of a document that is a mail.
+pub struct MailHeaders {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<chrono::NaiveDateTime>,
+    pub message_id: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Metadata {
+    /// A better title than the file name, e.g. the subject of a mail.
+    pub title: Option<String>,
+    pub mail: Option<MailHeaders>,
+    /// Files that came with the document, ingested as its children, by file name.
+    pub attachments: Vec<(String, Vec<u8>)>,
+}
+
+/// Everything but `sniff` and `to_pdf` defaults to treating the PDF rendition like any
+/// uploaded PDF: text layer plus OCR, an `ocrmypdf` archive and the first page as thumbnail.
+#[async_trait(?Send)]
+pub trait DocumentParser: Send + Sync {
+    /// The type of a file this parser handles, told from its first [`filetype::SNIFF_LEN`]
+    /// bytes and its name, or `None` if it is not one of them.
+    fn sniff(&self, header: &[u8], filename: &str) -> Option<FileType>;
+
+    /// The upload as a PDF, which the remaining steps work on, or `None` if it is a PDF already.
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>>;
+
+    /// The text of every page of the PDF rendition, in order.
+    async fn extract_text(&self, source: &Source, pdf: &Path) -> ParseResult<Vec<String>> {
+        let pages = ocr::text_layer(pdf, source.ocr_config).await;
+
+        (source.progress)(TaskStatus::Ocr);
+
+        Ok(ocr::ocr_text(pdf, pages, source.ocr_config))
+    }
+
+    /// Write the PDF to keep as the archive version to `output`.
+    async fn archive(&self, source: &Source, pdf: &Path, output: &Path) -> ParseResult<()> {
+        ocr::create_archive(pdf, output, source.ocr_config).await
+    }
+
+    /// [`THUMBNAIL_WIDTH`] pixels wide.
+    ///
+    /// Not async so should be run on a blocking thread pool
+    fn thumbnail(&self, _source: &Source, pdf: &Path) -> ParseResult<RgbImage> {
+        utils::render_pdf_page(&pdf, 0, THUMBNAIL_WIDTH, None)
+            .map_err(|e| format!("Failed to render thumbnail: {}", e))?
+            .ok_or_else(|| "The PDF has no pages".into())
+    }
+
+    fn metadata(&self, _source: &Source) -> ParseResult<Metadata> {
+        Ok(Metadata::default())
+    }
+}
+
+pub struct ParserRegistry {
+    parsers: Vec<Box<dyn DocumentParser>>,
+}
+
+impl ParserRegistry {
+    pub fn new() -> Self {
+        ParserRegistry {
+            parsers: Vec::new(),
+        }
+    }
+
+    /// Add a parser. Parsers registered later are asked first, so they win over earlier ones
+    /// that would take the same file.
+    pub fn register(&mut self, parser: impl DocumentParser + 'static) {
+        self.parsers.push(Box::new(parser));
+    }
+
+    /// The parser for a file and what it took the file for, or `None` if no parser takes it.
+    pub fn sniff(
+        &self,
+        path: &Path,
+        filename: &str,
+    ) -> std::io::Result<Option<(&dyn DocumentParser, FileType)>> {
+        let header = filetype::read_header(path)?;
+
+        Ok(self.parsers.iter().rev().find_map(|parser| {
+            parser
+                .sniff(&header, filename)
+                .map(|file_type| (parser.as_ref(), file_type))
+        }))
+    }
+}
+
+impl Default for ParserRegistry {
+    /// All the built-in parsers.
+    fn default() -> Self {
+        let mut registry = ParserRegistry::new();
+        // first, as most files it takes start like text, e.g. PDFs and mails
+        registry.register(text::TextParser);
+        registry.register(pdf::PdfParser);
+        registry.register(images::ImageParser);
+        registry.register(eml::EmlParser);
+        #[cfg(feature = "office")]
+        registry.register(office::OfficeParser);
+        registry
+    }
+}
+
+/// Render text to a PDF on disk, to archive and thumbnail documents that are text like any
+/// other. [`utils::text_pages`] gives the text of its pages.
+fn render_text(text: &str) -> ParseResult<NamedTempFile> {
+    let pdf = utils::text_to_pdf(text).map_err(|e| format!("Failed to render text: {}", e))?;
+
+    let output = NamedTempFile::new()?;
+    std::fs::write(output.path(), pdf)?;
+
+    Ok(output)
+}
+
+/// Keep a PDF rendered from text as the archive, there is nothing for OCR to add.
+async fn copy_archive(pdf: &Path, output: &Path) -> ParseResult<()> {
+    tokio::fs::copy(pdf, output).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn sniff(contents: &[u8], filename: &str) -> Option<&'static str> {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents).unwrap();
+
+        ParserRegistry::default()
+            .sniff(file.path(), filename)
+            .unwrap()
+            .map(|(_, file_type)| file_type.mime_type)
+    }
+
+    #[test]
+    fn sniffs_uploads_by_their_contents() {
+        let mail = b"From: Alice <alice@example.com>\r\nSubject: Invoice\r\n\r\nHi";
+
+        assert_eq!(sniff(b"%PDF-1.7\n", "notes.txt"), Some("application/pdf"));
+        assert_eq!(sniff(b"\xff\xd8\xff\xe0", "scan"), Some("image/jpeg"));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n", "scan.jpg"), Some("image/png"));
+        assert_eq!(sniff(b"II*\0", "scan"), Some("image/tiff"));
+        assert_eq!(sniff(mail, "mail.txt"), Some("message/rfc822"));
+        assert_eq!(
+            sniff("Café au lait".as_bytes(), "upload"),
+            Some("text/plain")
+        );
+        assert_eq!(sniff(b"# Notes", "notes.md"), Some("text/markdown"));
+        assert_eq!(sniff(b"<!DOCTYPE html><p>Hi", "page"), Some("text/html"));
+        assert_eq!(sniff(b"", "empty.txt"), None);
+        assert_eq!(sniff(b"\x7fELF\x02\0", "program"), None);
+    }
+
+    #[test]
+    fn prefers_parsers_registered_later() {
+        struct Everything;
+
+        #[async_trait(?Send)]
+        impl DocumentParser for Everything {
+            fn sniff(&self, _header: &[u8], _filename: &str) -> Option<FileType> {
+                Some(FileType {
+                    mime_type: "application/x-everything",
+                    extensions: &["bin"],
+                })
+            }
+
+            async fn to_pdf(&self, _source: &Source) -> ParseResult<Option<NamedTempFile>> {
+                Ok(None)
+            }
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"%PDF-1.7\n").unwrap();
+
+        let mut registry = ParserRegistry::default();
+        registry.register(Everything);
+
+        let (_, file_type) = registry.sniff(file.path(), "a.pdf").unwrap().unwrap();
+        assert_eq!(file_type.mime_type, "application/x-everything");
+    }
+}
@@ -0,0 +1,261 @@
+// Office documents, converted to PDF with LibreOffice. Only built with the `office` feature.
+
+use std::path::Path;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tempfile::NamedTempFile;
+use tokio::process::Command;
+
+use super::{DocumentParser, ParseResult, Source};
+use crate::filetype::FileType;
+
+/// How long a single conversion may take before it is killed.
+const OFFICE_TIMEOUT: Duration = Duration::from_secs(180);
+
+const DOCX: FileType = FileType {
+    mime_type: "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    extensions: &["docx"],
+};
+
+const XLSX: FileType = FileType {
+    mime_type: "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    extensions: &["xlsx"],
+};
+
+const ODT: FileType = FileType {
+    mime_type: "application/vnd.oasis.opendocument.text",
+    extensions: &["odt"],
+};
+
+pub struct OfficeParser;
+
+#[async_trait(?Send)]
+impl DocumentParser for OfficeParser {
+    /// Office documents are ZIP archives. OpenDocument files start with an uncompressed
+    /// `mimetype` entry, Office Open XML files have their parts in a folder named after the
+    /// application, usually close to the start.
+    fn sniff(&self, header: &[u8], filename: &str) -> Option<FileType> {
+        if !header.starts_with(b"PK\x03\x04") {
+            return None;
+        }
+
+        let contains = |needle: &[u8]| header.windows(needle.len()).any(|window| window == needle);
+
+        if contains(b"mimetypeapplication/vnd.oasis.opendocument.text") {
+            Some(ODT)
+        } else if contains(b"word/") {
+            Some(DOCX)
+        } else if contains(b"xl/") {
+            Some(XLSX)
+        } else {
+            [DOCX, XLSX, ODT]
+                .into_iter()
+                .find(|file_type| file_type.has_extension(filename))
+        }
+    }
+
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>> {
+        // the extension LibreOffice needs to recognise the file
+        let extension = source.file_type.extensions[0];
+
+        Ok(Some(office_to_pdf(source.path, extension).await?))
+    }
+}
+
+/// Convert an office document to PDF with LibreOffice, or whatever `SOFFICE_PATH` points to. The
+/// converter is called like `soffice --headless --convert-to pdf --outdir <dir> <file>` and
+/// must leave `<dir>/<file stem>.pdf` behind.
+async fn office_to_pdf(path: &Path, extension: &str) -> ParseResult<NamedTempFile> {
+    let soffice = std::env::var("SOFFICE_PATH").unwrap_or_else(|_| "soffice".to_string());
+
+    convert(&soffice, path, extension, OFFICE_TIMEOUT).await
+}
+
+async fn convert(
+    soffice: &str,
+    path: &Path,
+    extension: &str,
+    timeout: Duration,
+) -> ParseResult<NamedTempFile> {
+    let workdir = tempfile::tempdir()?;
+
+    // LibreOffice goes by the extension, which the staged upload does not have
+    let input = workdir.path().join(format!("document.{}", extension));
+    tokio::fs::copy(path, &input).await?;
+
+    let outdir = workdir.path().join("out");
+
+    // a profile of its own, as concurrent instances sharing one block each other
+    let profile = format!(
+        "-env:UserInstallation=file://{}",
+        workdir.path().join("profile").display()
+    );
+
+    let command = Command::new(soffice)
+        .arg(profile)
+        .args(["--headless", "--convert-to", "pdf", "--outdir"])
+        .arg(&outdir)
+        .arg(&input)
+        .kill_on_drop(true)
+        .output();
+
+    let result = tokio::time::timeout(timeout, command)
+        .await
+        .map_err(|_| format!("{} took longer than {:?}", soffice, timeout))?
+        .map_err(|e| format!("Failed to run {}: {}", soffice, e))?;
+
+    let converted = outdir.join("document.pdf");
+
+    if !result.status.success() || !converted.exists() {
+        return Err(format!(
+            "{} failed with {}: {}",
+            soffice,
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    let output = NamedTempFile::new()?;
+    tokio::fs::copy(&converted, output.path()).await?;
+
+    Ok(output)
+}
+
+#[cfg(all(test, feature = "office"))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::LazyLock;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Stand-ins for LibreOffice, all written before any of them runs, as executing a file
+    /// while another thread still has it open for writing fails with "Text file busy".
+    static STUBS: LazyLock<TempDir> = LazyLock::new(|| {
+        let dir = tempfile::tempdir().unwrap();
+
+        let stubs = [
+            // writes `<outdir>/<input stem>.pdf` like the real one
+            (
+                "convert",
+                r#"while [ $# -gt 1 ]; do
+  if [ "$1" = "--outdir" ]; then outdir="$2"; fi
+  shift
+done
+mkdir -p "$outdir"
+name=$(basename "$1")
+printf '%%PDF-1.4 converted' > "$outdir/${name%.*}.pdf""#,
+            ),
+            ("fail", "echo 'source file could not be loaded' >&2\nexit 1"),
+            // exits fine without converting anything
+            ("noop", "exit 0"),
+            ("hang", "exec sleep 30"),
+        ];
+
+        for (name, script) in stubs {
+            let path = dir.path().join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        dir
+    });
+
+    fn stub(name: &str) -> String {
+        STUBS.path().join(name).to_str().unwrap().to_string()
+    }
+
+    fn input() -> NamedTempFile {
+        let input = NamedTempFile::new().unwrap();
+        std::fs::write(input.path(), b"PK not really a docx").unwrap();
+        input
+    }
+
+    #[tokio::test]
+    async fn returns_what_the_converter_wrote() {
+        let input = input();
+
+        let output = convert(&stub("convert"), input.path(), "docx", OFFICE_TIMEOUT)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(output.path()).unwrap(), b"%PDF-1.4 converted");
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_output_of_the_converter() {
+        let input = input();
+
+        let error = convert(&stub("fail"), input.path(), "docx", OFFICE_TIMEOUT)
+            .await
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("exit status: 1"), "{}", error);
+        assert!(
+            error.contains("source file could not be loaded"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_when_nothing_was_converted() {
+        let input = input();
+
+        let result = convert(&stub("noop"), input.path(), "odt", OFFICE_TIMEOUT).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_converter_that_hangs() {
+        let input = input();
+        let started = std::time::Instant::now();
+
+        let error = convert(
+            &stub("hang"),
+            input.path(),
+            "xlsx",
+            Duration::from_millis(200),
+        )
+        .await
+        .unwrap_err()
+        .to_string();
+
+        assert!(error.contains("took longer than"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn sniffs_office_documents_in_their_zip_archives() {
+        let zip = |entry: &[u8]| [b"PK\x03\x04\x14\0\0\0".as_slice(), entry].concat();
+
+        let cases: [(&[u8], &str, Option<FileType>); 7] = [
+            (
+                &zip(b"mimetypeapplication/vnd.oasis.opendocument.text"),
+                "upload",
+                Some(ODT),
+            ),
+            (&zip(b"word/document.xml"), "upload", Some(DOCX)),
+            (&zip(b"xl/workbook.xml"), "upload", Some(XLSX)),
+            // the parts can come later than the header, then the name decides
+            (&zip(b"[Content_Types].xml"), "report.DOCX", Some(DOCX)),
+            (&zip(b"[Content_Types].xml"), "photos.zip", None),
+            (b"%PDF-1.7", "report.docx", None),
+            (b"", "report.odt", None),
+        ];
+
+        for (header, filename, expected) in cases {
+            assert_eq!(
+                OfficeParser.sniff(header, filename),
+                expected,
+                "{}",
+                filename
+            );
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Uploaded PDFs, which every default of `DocumentParser` is made for. Encrypted ones are
+// decrypted first, so the archive and everything after it can be read without a password.
+
+use std::io::Write;
+use std::path::Path;
+
+use async_trait::async_trait;
+use tempfile::NamedTempFile;
+use tokio::process::Command;
+
+use super::{DocumentParser, ParseResult, Source};
+use crate::filetype::FileType;
+use crate::utils;
+
+const PDF: FileType = FileType {
+    mime_type: "application/pdf",
+    extensions: &["pdf"],
+};
+
+pub struct PdfParser;
+
+#[async_trait(?Send)]
+impl DocumentParser for PdfParser {
+    fn sniff(&self, header: &[u8], _filename: &str) -> Option<FileType> {
+        header.starts_with(b"%PDF-").then_some(PDF)
+    }
+
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>> {
+        match utils::find_pdf_password(source.path, source.passwords)? {
+            Some(password) => Ok(Some(decrypt(source.path, &password).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A decrypted copy of the PDF, made with [`qpdf`](https://qpdf.readthedocs.io/).
+async fn decrypt(path: &Path, password: &str) -> ParseResult<NamedTempFile> {
+    let output = NamedTempFile::new()?;
+
+    // not on the command line, where other users could see it
+    let mut password_file = NamedTempFile::new()?;
+    password_file.write_all(password.as_bytes())?;
+
+    let result = Command::new("qpdf")
+        .arg(format!(
+            "--password-file={}",
+            password_file.path().display()
+        ))
+        .arg("--decrypt")
+        .arg(path)
+        .arg(output.path())
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run qpdf: {}", e))?;
+
+    // 3 means it succeeded with warnings, common enough with PDFs from the wild
+    if !matches!(result.status.code(), Some(0) | Some(3)) {
+        return Err(format!(
+            "qpdf failed with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(output)
+}
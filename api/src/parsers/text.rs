@@ -0,0 +1,128 @@
+// Plain text, Markdown and HTML, indexed as they are and rendered to a PDF for the archive
+
+use std::path::Path;
+
+use async_trait::async_trait;
+use tempfile::NamedTempFile;
+
+use super::{DocumentParser, ParseResult, Source, copy_archive, render_text};
+use crate::filetype::{FileType, SNIFF_LEN};
+use crate::utils;
+
+const TEXT: FileType = FileType {
+    mime_type: "text/plain",
+    extensions: &["txt", "text"],
+};
+
+/// Looks like plain text, only the name tells it apart.
+const MARKDOWN: FileType = FileType {
+    mime_type: "text/markdown",
+    extensions: &["md", "markdown"],
+};
+
+const HTML: FileType = FileType {
+    mime_type: "text/html",
+    extensions: &["html", "htm"],
+};
+
+pub struct TextParser;
+
+#[async_trait(?Send)]
+impl DocumentParser for TextParser {
+    fn sniff(&self, header: &[u8], filename: &str) -> Option<FileType> {
+        if !is_text(header, filename) {
+            None
+        } else if looks_like_html(header) {
+            Some(HTML)
+        } else if MARKDOWN.has_extension(filename) {
+            Some(MARKDOWN)
+        } else {
+            Some(TEXT)
+        }
+    }
+
+    async fn to_pdf(&self, source: &Source) -> ParseResult<Option<NamedTempFile>> {
+        let text = read_text(source.path, source.file_type)?;
+        Ok(Some(render_text(&text)?))
+    }
+
+    async fn extract_text(&self, source: &Source, _pdf: &Path) -> ParseResult<Vec<String>> {
+        let text = read_text(source.path, source.file_type)?;
+        Ok(utils::text_pages(&text))
+    }
+
+    async fn archive(&self, _source: &Source, pdf: &Path, output: &Path) -> ParseResult<()> {
+        copy_archive(pdf, output).await
+    }
+}
+
+/// The text of a text document, with the markup of HTML stripped.
+fn read_text(path: &Path, file_type: FileType) -> ParseResult<String> {
+    let text = decode(std::fs::read(path)?);
+    let text = text.trim_start_matches('\u{feff}');
+
+    if file_type == HTML {
+        Ok(html2text::from_read(text.as_bytes(), 100)?)
+    } else {
+        Ok(text.to_string())
+    }
+}
+
+/// UTF-8, or else Latin-1, which every byte is valid in and most other text that is not UTF-8
+/// comes close to.
+fn decode(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes)
+        .unwrap_or_else(|e| e.into_bytes().into_iter().map(char::from).collect())
+}
+
+/// UTF-8 without NUL bytes. The header may end in the middle of a character. Files named like
+/// text are taken in other encodings too, e.g. Latin-1 from older systems.
+fn is_text(header: &[u8], filename: &str) -> bool {
+    if header.is_empty() || header.contains(&0) {
+        return false;
+    }
+
+    match std::str::from_utf8(header) {
+        Ok(_) => true,
+        Err(e) if e.error_len().is_none() && header.len() == SNIFF_LEN => true,
+        Err(_) => [TEXT, MARKDOWN, HTML]
+            .iter()
+            .any(|file_type| file_type.has_extension(filename)),
+    }
+}
+
+fn looks_like_html(header: &[u8]) -> bool {
+    let start = String::from_utf8_lossy(header)
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .to_lowercase();
+
+    ["<!doctype html", "<html", "<head", "<body"]
+        .iter()
+        .any(|tag| start.starts_with(tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_latin1_when_it_is_not_utf8() {
+        assert_eq!(decode("Café".as_bytes().to_vec()), "Café");
+        assert_eq!(decode(b"Caf\xe9 \xa7 3".to_vec()), "Café § 3");
+    }
+
+    #[test]
+    fn takes_other_encodings_only_when_named_like_text() {
+        let latin1 = b"Caf\xe9 au lait, 12 \xa7 3";
+
+        assert_eq!(TextParser.sniff(latin1, "menu.txt"), Some(TEXT));
+        assert_eq!(TextParser.sniff(latin1, "MENU.TXT"), Some(TEXT));
+        assert_eq!(TextParser.sniff(latin1, "menu.md"), Some(MARKDOWN));
+        assert_eq!(TextParser.sniff(b"<html>Caf\xe9", "menu.htm"), Some(HTML));
+        assert_eq!(TextParser.sniff(latin1, "menu"), None);
+        assert_eq!(TextParser.sniff(latin1, "menu.bin"), None);
+        // NUL bytes still make it binary, whatever the name
+        assert_eq!(TextParser.sniff(b"Caf\xe9\0", "menu.txt"), None);
+    }
+}
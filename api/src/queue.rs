@@ -0,0 +1,285 @@
+// Queueing of staged files for the ingestion worker, shared by every way documents come in
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use diesel::{
+    Connection, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult,
+    RunQueryDsl, SelectableHelper,
+};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::events::Event;
+use crate::models::{NewTask, Task, TaskStatus};
+use crate::ocr::OcrConfig;
+use crate::schema::{documents, tasks};
+use crate::{AppState, UPLOAD_PATH_RAW};
+
+/// What to do with an upload whose checksum matches an existing document.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Refuse the whole upload.
+    #[default]
+    Reject,
+    /// Skip ingestion and resolve the upload to the existing document.
+    Link,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "link" => Ok(DuplicatePolicy::Link),
+            other => Err(format!(
+                "Unknown duplicate policy `{}`, expected reject or link",
+                other
+            )),
+        }
+    }
+}
+
+/// A file that has been written to the upload directory but is not queued yet. The staged file
+/// is deleted when this is dropped without being queued.
+pub struct Upload {
+    pub filename: String,
+    pub staged: NamedTempFile,
+    /// SHA-256 of the file, hex encoded.
+    pub checksum: String,
+    /// Names of the tags to assign once ingested.
+    pub tags: Vec<String>,
+}
+
+pub struct UploadOptions {
+    pub ocr_config: OcrConfig,
+    pub on_duplicate: DuplicatePolicy,
+    /// Link the ingested documents to this one, e.g. attachments to their mail.
+    pub parent_id: Option<String>,
+    /// Password to open encrypted PDFs with.
+    pub password: Option<String>,
+}
+
+pub enum QueueError {
+    Duplicate {
+        filename: String,
+        document_id: String,
+    },
+    Internal(String),
+}
+
+/// For transactions, which may also fail on their own, e.g. when committing.
+impl From<diesel::result::Error> for QueueError {
+    fn from(e: diesel::result::Error) -> Self {
+        QueueError::Internal(e.to_string())
+    }
+}
+
+/// A new, empty staging file in the upload directory.
+pub fn staging_file() -> std::io::Result<NamedTempFile> {
+    NamedTempFile::new_in(UPLOAD_PATH_RAW)
+}
+
+/// Copy a file from disk into the upload directory, hashing it along the way.
+pub async fn stage_file(path: &Path, tags: Vec<String>) -> std::io::Result<Upload> {
+    let staged = staging_file()?;
+
+    let mut source = tokio::fs::File::open(path).await?;
+    let mut target = tokio::fs::File::create(staged.path()).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = source.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+        target.write_all(&buf[..read]).await?;
+    }
+
+    target.flush().await?;
+
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "document.pdf".to_string());
+
+    Ok(Upload {
+        filename,
+        staged,
+        checksum: format!("{:x}", hasher.finalize()),
+        tags,
+    })
+}
+
+/// Write an in-memory file (e.g. a mail attachment) to the upload directory.
+pub async fn stage_bytes(
+    filename: String,
+    bytes: &[u8],
+    tags: Vec<String>,
+) -> std::io::Result<Upload> {
+    let staged = staging_file()?;
+
+    tokio::fs::write(staged.path(), bytes).await?;
+
+    Ok(Upload {
+        filename,
+        staged,
+        checksum: format!("{:x}", Sha256::digest(bytes)),
+        tags,
+    })
+}
+
+/// The id of the document with this checksum, whether it is already ingested or still queued.
+pub fn find_duplicate(conn: &mut PgConnection, checksum: &str) -> QueryResult<Option<String>> {
+    let document = documents::table
+        .filter(documents::checksum.eq(checksum))
+        .select(documents::id)
+        .first::<String>(conn)
+        .optional()?;
+
+    if document.is_some() {
+        return Ok(document);
+    }
+
+    let pending: Vec<&str> = std::iter::once(&TaskStatus::Queued)
+        .chain(TaskStatus::IN_PROGRESS.iter())
+        .map(TaskStatus::as_str)
+        .collect();
+
+    tasks::table
+        .filter(tasks::checksum.eq(checksum))
+        .filter(tasks::status.eq_any(pending))
+        .select(tasks::document_id)
+        .first::<String>(conn)
+        .optional()
+}
+
+/// Queue every upload for the ingestion worker, one task per file. Either all uploads are
+/// queued or, if one of them is a rejected duplicate, none of them are.
+pub fn enqueue(
+    state: &AppState,
+    uploads: Vec<Upload>,
+    options: &UploadOptions,
+) -> Result<Vec<Task>, QueueError> {
+    let internal = |e: &dyn std::fmt::Display| QueueError::Internal(e.to_string());
+
+    let mut conn = state.db_pool.get().map_err(|e| internal(&e))?;
+
+    // checksum -> document id, so a file sent twice in one upload is a duplicate as well
+    let mut known: HashMap<String, String> = HashMap::new();
+    let mut new_tasks = Vec::with_capacity(uploads.len());
+    let mut staged: Vec<(NamedTempFile, PathBuf)> = Vec::new();
+
+    for upload in uploads {
+        let duplicate_of = match known.get(&upload.checksum) {
+            Some(document_id) => Some(document_id.clone()),
+            None => find_duplicate(&mut conn, &upload.checksum).map_err(|e| internal(&e))?,
+        };
+
+        let task_id = uuid::Uuid::new_v4().to_string();
+
+        if let Some(document_id) = duplicate_of {
+            if options.on_duplicate == DuplicatePolicy::Reject {
+                return Err(QueueError::Duplicate {
+                    filename: upload.filename,
+                    document_id,
+                });
+            }
+
+            println!(
+                "`{}` is a duplicate of {}, linking",
+                upload.filename, document_id
+            );
+
+            new_tasks.push(NewTask {
+                id: task_id,
+                document_id,
+                filename: upload.filename,
+                file_path: String::new(),
+                ocr_mode: options.ocr_config.mode.to_string(),
+                ocr_languages: options.ocr_config.languages.clone(),
+                status: TaskStatus::Done.as_str().to_string(),
+                checksum: Some(upload.checksum),
+                finished_at: Some(chrono::Utc::now().naive_utc()),
+                tags: upload.tags,
+                parent_id: options.parent_id.clone(),
+                password: None,
+            });
+
+            continue;
+        }
+
+        let document_id = uuid::Uuid::new_v4().to_string();
+        let file_path = Path::new(UPLOAD_PATH_RAW).join(&task_id);
+
+        known.insert(upload.checksum.clone(), document_id.clone());
+
+        new_tasks.push(NewTask {
+            id: task_id,
+            document_id,
+            filename: upload.filename,
+            file_path: file_path.to_string_lossy().to_string(),
+            ocr_mode: options.ocr_config.mode.to_string(),
+            ocr_languages: options.ocr_config.languages.clone(),
+            status: TaskStatus::Queued.as_str().to_string(),
+            checksum: Some(upload.checksum),
+            finished_at: None,
+            tags: upload.tags,
+            parent_id: options.parent_id.clone(),
+            password: options.password.clone(),
+        });
+
+        staged.push((upload.staged, file_path));
+    }
+
+    let mut persisted: Vec<PathBuf> = Vec::new();
+
+    // the files are kept before the tasks pointing at them are committed, so the worker never
+    // sees a task without its file, and removed again if the tasks do not make it
+    let result = conn.transaction::<_, QueueError, _>(|conn| {
+        let queued = diesel::insert_into(tasks::table)
+            .values(&new_tasks)
+            .returning(Task::as_returning())
+            .get_results(conn)
+            .map_err(|e| internal(&e))?;
+
+        for (tmp, file_path) in staged {
+            tmp.persist(&file_path).map_err(|e| internal(&e))?;
+            persisted.push(file_path);
+        }
+
+        Ok(queued)
+    });
+
+    let queued = match result {
+        Ok(queued) => queued,
+        Err(e) => {
+            for file_path in &persisted {
+                if let Err(e) = std::fs::remove_file(file_path) {
+                    println!(
+                        "Failed to remove staged upload {}: {}",
+                        file_path.display(),
+                        e
+                    );
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    state.task_notify.notify_one();
+
+    for task in &queued {
+        state.events.publish(Event::TaskUpdated {
+            task: Box::new(task.clone()),
+        });
+    }
+
+    Ok(queued)
+}
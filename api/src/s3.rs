@@ -1,9 +1,9 @@
 // utility wrapper functions to interact with the S3 API
 
-use std::{error::Error, time::Duration};
+use std::error::Error;
 
 use aws_sdk_s3::{
-    operation::{delete_object::DeleteObjectOutput, get_object::GetObjectOutput}, presigning::PresigningConfig, primitives::ByteStream, Client
+    operation::{delete_object::DeleteObjectOutput, get_object::GetObjectOutput}, primitives::ByteStream, Client
 };
 
 pub struct S3Client {
@@ -80,28 +80,6 @@ impl S3Client {
             .map_err(Into::into)
     }
 
-    /// Generate a URL for a presigned GET request.
-    pub async fn get_object_url(
-        &self,
-        key: &str,
-        expires_in: u64,
-    ) -> Result<String, Box<dyn Error>> {
-        let expires_in = Duration::from_secs(expires_in);
-        let presigned_request = self
-            .client
-            .get_object()
-            .bucket(&self.bucket)
-            .key(key)
-            .presigned(PresigningConfig::expires_in(expires_in)?)
-            .await?;
-
-        println!("Object URI: {}", presigned_request.uri());
-        let valid_until = chrono::offset::Local::now() + expires_in;
-        println!("Valid until: {valid_until}");
-
-        Ok(presigned_request.uri().to_string())
-    }
-
     pub async fn delete_object(&self, key: &str) -> Result<DeleteObjectOutput, Box<dyn Error>> {
         self.client
             .delete_object()
@@ -112,6 +90,30 @@ impl S3Client {
             .map_err(Into::into)
     }
 
+    /// Keys of all objects whose key starts with `prefix`.
+    pub async fn list_keys(&self, prefix: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut keys = Vec::new();
+
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+
+        while let Some(page) = pages.next().await {
+            keys.extend(
+                page?
+                    .contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(str::to_string)),
+            );
+        }
+
+        Ok(keys)
+    }
+
     pub async fn get_object(&self, key: &str) -> Result<GetObjectOutput, Box<dyn Error>> {
         self.client
             .get_object()
@@ -1,10 +1,175 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    correspondents (id) {
+        id -> Int4,
+        name -> Varchar,
+        matching -> Varchar,
+        matching_algorithm -> Varchar,
+        is_insensitive -> Bool,
+    }
+}
+
+diesel::table! {
+    custom_field_values (document_id, field_id) {
+        document_id -> Varchar,
+        field_id -> Int4,
+        value_text -> Nullable<Varchar>,
+        value_integer -> Nullable<Int8>,
+        value_monetary -> Nullable<Int8>,
+        value_date -> Nullable<Date>,
+        value_boolean -> Nullable<Bool>,
+        value_document_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    custom_fields (id) {
+        id -> Int4,
+        name -> Varchar,
+        data_type -> Varchar,
+        select_options -> Array<Text>,
+    }
+}
+
+diesel::table! {
+    document_pages (document_id, page_number) {
+        document_id -> Varchar,
+        page_number -> Int4,
+        body -> Text,
+    }
+}
+
+diesel::table! {
+    document_tags (document_id, tag_id) {
+        document_id -> Varchar,
+        tag_id -> Int4,
+    }
+}
+
+diesel::table! {
+    document_types (id) {
+        id -> Int4,
+        name -> Varchar,
+        matching -> Varchar,
+        matching_algorithm -> Varchar,
+        is_insensitive -> Bool,
+    }
+}
+
 diesel::table! {
     documents (id) {
         id -> Varchar,
         title -> Varchar,
         body -> Text,
         thumbnail_url -> Varchar,
+        checksum -> Nullable<Varchar>,
+        mime_type -> Varchar,
+        extension -> Varchar,
+        parent_id -> Nullable<Varchar>,
+        created -> Nullable<Date>,
+        added -> Timestamp,
+        correspondent_id -> Nullable<Int4>,
+        document_type_id -> Nullable<Int4>,
+    }
+}
+
+diesel::table! {
+    mail_accounts (id) {
+        id -> Int4,
+        name -> Varchar,
+        host -> Varchar,
+        port -> Int4,
+        security -> Varchar,
+        username -> Varchar,
+        password -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    mail_rules (id) {
+        id -> Int4,
+        account_id -> Int4,
+        name -> Varchar,
+        folder -> Varchar,
+        filter_from -> Nullable<Varchar>,
+        filter_subject -> Nullable<Varchar>,
+        max_age_days -> Nullable<Int4>,
+        scope -> Varchar,
+        action -> Varchar,
+        action_parameter -> Nullable<Varchar>,
+        tags -> Array<Text>,
+        enabled -> Bool,
+    }
+}
+
+diesel::table! {
+    mails (document_id) {
+        document_id -> Varchar,
+        from_address -> Nullable<Varchar>,
+        to_address -> Nullable<Varchar>,
+        subject -> Nullable<Varchar>,
+        sent_at -> Nullable<Timestamp>,
+        message_id -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Int4,
+        name -> Varchar,
+        color -> Varchar,
+        parent_id -> Nullable<Int4>,
+        matching -> Varchar,
+        matching_algorithm -> Varchar,
+        is_insensitive -> Bool,
+    }
+}
+
+diesel::table! {
+    tasks (id) {
+        id -> Varchar,
+        document_id -> Varchar,
+        filename -> Varchar,
+        file_path -> Varchar,
+        ocr_mode -> Varchar,
+        ocr_languages -> Varchar,
+        status -> Varchar,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        started_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+        checksum -> Nullable<Varchar>,
+        tags -> Array<Text>,
+        parent_id -> Nullable<Varchar>,
+        password -> Nullable<Varchar>,
+        claimed_by -> Nullable<Varchar>,
+        heartbeat_at -> Nullable<Timestamp>,
     }
 }
+
+diesel::joinable!(custom_field_values -> custom_fields (field_id));
+diesel::joinable!(document_pages -> documents (document_id));
+diesel::joinable!(document_tags -> documents (document_id));
+diesel::joinable!(document_tags -> tags (tag_id));
+diesel::joinable!(documents -> correspondents (correspondent_id));
+diesel::joinable!(documents -> document_types (document_type_id));
+diesel::joinable!(mail_rules -> mail_accounts (account_id));
+diesel::joinable!(mails -> documents (document_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    correspondents,
+    custom_field_values,
+    custom_fields,
+    document_pages,
+    document_tags,
+    document_types,
+    documents,
+    mail_accounts,
+    mail_rules,
+    mails,
+    tags,
+    tasks,
+);
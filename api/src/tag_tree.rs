@@ -0,0 +1,182 @@
+// Tags form a tree through `parent_id`, and a tag is named by its path from the top, e.g.
+// `Finance/Tax`. Filtering by a tag includes the documents of all tags below it.
+
+use std::collections::HashMap;
+
+use diesel::result::Error as DieselError;
+use diesel::{
+    ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+    SelectableHelper,
+};
+
+use crate::models::{DocumentTag, NewTag, Tag};
+use crate::schema::{document_tags, tags};
+
+pub fn load_all(conn: &mut PgConnection) -> QueryResult<Vec<Tag>> {
+    tags::table
+        .select(Tag::as_select())
+        .order(tags::name)
+        .load(conn)
+}
+
+/// The names from the top of the tree down to the tag, e.g. `["Finance", "Tax"]`.
+pub fn path(all: &[Tag], id: i32) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut current = all.iter().find(|tag| tag.id == id);
+
+    while let Some(tag) = current {
+        names.push(tag.name.clone());
+
+        // a tree is never deeper than it has tags, this only guards against a cycle
+        if names.len() > all.len() {
+            break;
+        }
+
+        current = tag
+            .parent_id
+            .and_then(|parent_id| all.iter().find(|tag| tag.id == parent_id));
+    }
+
+    names.reverse();
+    names
+}
+
+/// The tag and every tag below it.
+pub fn descendants(all: &[Tag], id: i32) -> Vec<i32> {
+    let mut ids = vec![id];
+    let mut index = 0;
+
+    while index < ids.len() {
+        let parent_id = ids[index];
+
+        ids.extend(
+            all.iter()
+                .filter(|tag| tag.parent_id == Some(parent_id) && !ids.contains(&tag.id))
+                .map(|tag| tag.id)
+                .collect::<Vec<i32>>(),
+        );
+
+        index += 1;
+    }
+
+    ids
+}
+
+/// Names may not contain the `/` that separates them in paths.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("A tag needs a name".to_string());
+    }
+
+    if name.contains('/') {
+        return Err(format!("Tag names cannot contain `/`: `{}`", name));
+    }
+
+    Ok(())
+}
+
+/// Colors are given as `#rrggbb`.
+pub fn validate_color(color: &str) -> Result<(), String> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid color `{}`, expected e.g. #a6cee3", color))
+    }
+}
+
+/// The id of the tag at a path like `Finance/Tax`, creating the tags along it that do not
+/// exist yet.
+pub fn find_or_create(conn: &mut PgConnection, path: &str) -> QueryResult<i32> {
+    let mut parent_id: Option<i32> = None;
+
+    for name in path
+        .split('/')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let mut query = tags::table
+            .filter(tags::name.eq(name))
+            .select(tags::id)
+            .into_boxed();
+
+        query = match parent_id {
+            Some(parent_id) => query.filter(tags::parent_id.eq(parent_id)),
+            None => query.filter(tags::parent_id.is_null()),
+        };
+
+        let id = match query.first::<i32>(conn).optional()? {
+            Some(id) => id,
+            None => diesel::insert_into(tags::table)
+                .values(&NewTag {
+                    name: name.to_string(),
+                    color: None,
+                    parent_id,
+                    matching: None,
+                    matching_algorithm: None,
+                    is_insensitive: None,
+                })
+                .returning(tags::id)
+                .get_result(conn)?,
+        };
+
+        parent_id = Some(id);
+    }
+
+    parent_id.ok_or_else(|| DieselError::QueryBuilderError("Empty tag path".into()))
+}
+
+/// Replace the tags of a document.
+pub fn assign(conn: &mut PgConnection, document_id: &str, tag_ids: &[i32]) -> QueryResult<()> {
+    diesel::delete(document_tags::table.filter(document_tags::document_id.eq(document_id)))
+        .execute(conn)?;
+
+    let rows: Vec<DocumentTag> = tag_ids
+        .iter()
+        .map(|tag_id| DocumentTag {
+            document_id: document_id.to_string(),
+            tag_id: *tag_id,
+        })
+        .collect();
+
+    diesel::insert_into(document_tags::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// The tag ids of each of the documents.
+pub fn by_document(
+    conn: &mut PgConnection,
+    document_ids: &[String],
+) -> QueryResult<HashMap<String, Vec<i32>>> {
+    let rows: Vec<DocumentTag> = document_tags::table
+        .filter(document_tags::document_id.eq_any(document_ids))
+        .select(DocumentTag::as_select())
+        .load(conn)?;
+
+    let mut by_document: HashMap<String, Vec<i32>> = HashMap::new();
+
+    for row in rows {
+        by_document
+            .entry(row.document_id)
+            .or_default()
+            .push(row.tag_id);
+    }
+
+    Ok(by_document)
+}
+
+/// Ids of the documents that have any of the tags.
+pub fn documents_with(conn: &mut PgConnection, tag_ids: &[i32]) -> QueryResult<Vec<String>> {
+    document_tags::table
+        .filter(document_tags::tag_id.eq_any(tag_ids))
+        .select(document_tags::document_id)
+        .distinct()
+        .load(conn)
+}
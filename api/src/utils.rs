@@ -1,10 +1,14 @@
 use crate::Command;
 use dotenvy::dotenv;
-use image::ImageBuffer;
-use pdfium_render::prelude::PdfPageRenderRotation;
+use image::codecs::webp::WebPEncoder;
+use image::{ExtendedColorType, ImageEncoder, ImageError, RgbImage};
+use pdfium_render::prelude::PdfPageObjectsCommon;
+use pdfium_render::prelude::PdfPagePaperSize;
+use pdfium_render::prelude::PdfPoints;
 use pdfium_render::prelude::PdfRenderConfig;
 use pdfium_render::prelude::Pdfium;
 use pdfium_render::prelude::PdfiumError;
+use pdfium_render::prelude::PdfiumInternalError;
 use std::path::Path;
 use tantivy::Term;
 use tantivy::query::BooleanQuery;
@@ -24,44 +28,206 @@ pub async fn pdf_to_string(path: &Path) -> String {
     return contents.to_string();
 }
 
-/// Not async so should be run on a blocking thread pool
-pub fn export_pdf_to_jpegs(
-    path: &impl AsRef<Path>,
-    password: Option<&str>,
-) -> Result<ImageBuffer<image::Rgb<u8>, Vec<u8>>, PdfiumError> {
-    // Renders each page in the PDF file at the given path to a separate JPEG file.
+/// Splits the `pdftotext` output into pages. Pages are separated by a form feed, so a page
+/// without a text layer (e.g. a scan) comes back as an empty string.
+pub async fn pdf_to_pages(path: &Path) -> Vec<String> {
+    let contents = pdf_to_string(path).await;
 
-    // Bind to a Pdfium library in the same directory as our Rust executable.
-    // See the "Dynamic linking" section below.
+    let mut pages: Vec<String> = contents.split('\x0c').map(str::to_string).collect();
 
+    // every page, including the last one, is terminated by a form feed
+    if pages.last().is_some_and(|page| page.is_empty()) {
+        pages.pop();
+    }
+
+    pages
+}
+
+/// Bind to the Pdfium library pointed to by the `PDFIUM_PATH` env var.
+pub fn bind_pdfium() -> Pdfium {
     dotenv().ok();
     let pdfium_path = std::env::var("PDFIUM_PATH").expect("Expected PDFIUM_PATH env var");
-    let pdfium = Pdfium::new(Pdfium::bind_to_library(&pdfium_path).unwrap());
+    Pdfium::new(Pdfium::bind_to_library(&pdfium_path).unwrap())
+}
+
+/// Pages are rendered no taller than this, narrower if need be, so a long receipt or a broken
+/// page size does not make a huge bitmap.
+const MAX_RENDER_HEIGHT: i32 = 4000;
 
-    // Load the document from the given path...
+/// Render a page of a PDF (counted from 0) `width` pixels wide, or `None` if the PDF has no
+/// such page.
+///
+/// Not async so should be run on a blocking thread pool
+pub fn render_pdf_page(
+    path: &impl AsRef<Path>,
+    page: u16,
+    width: u16,
+    password: Option<&str>,
+) -> Result<Option<RgbImage>, PdfiumError> {
+    let pdfium = bind_pdfium();
 
-    let document = pdfium
-        .load_pdf_from_file(path, password)
-        .expect("Expected to load file");
+    let document = pdfium.load_pdf_from_file(path, password)?;
 
-    // ... set rendering options that will be applied to all pages...
+    if page >= document.pages().len() {
+        return Ok(None);
+    }
 
     let render_config = PdfRenderConfig::new()
-        .set_target_width(2000)
-        .set_maximum_height(2000)
-        .rotate_if_landscape(PdfPageRenderRotation::Degrees90, true);
+        .set_target_width(width as i32)
+        .set_maximum_height(MAX_RENDER_HEIGHT);
+
+    let image = document
+        .pages()
+        .get(page)?
+        .render_with_config(&render_config)?
+        .as_image()
+        .into_rgb8();
 
-    // ... then render each page to a bitmap image, saving each image to a JPEG file.
+    Ok(Some(image))
+}
 
-    let first_page = document.pages().get(0);
+/// Encode an image as (lossless) WebP, which comes out a fraction of the size of a PNG.
+pub fn encode_webp(image: &RgbImage) -> Result<Vec<u8>, ImageError> {
+    let mut buf = Vec::new();
 
-    let bytes = first_page
-        .expect("Expected a first page")
-        .render_with_config(&render_config)?
-        .as_image() // Renders this page to an image::DynamicImage...
-        .into_rgb8(); // ... then converts it to an image::Image...
+    WebPEncoder::new_lossless(&mut buf).write_image(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+        ExtendedColorType::Rgb8,
+    )?;
+
+    Ok(buf)
+}
+
+/// Find out whether a PDF is encrypted and, if it is, which of the candidates opens it.
+/// Returns `None` for a PDF that opens without a password.
+///
+/// Not async so should be run on a blocking thread pool
+pub fn find_pdf_password(path: &Path, candidates: &[String]) -> Result<Option<String>, String> {
+    let pdfium = bind_pdfium();
+
+    match pdfium.load_pdf_from_file(path, None) {
+        Ok(_) => return Ok(None),
+        Err(PdfiumError::PdfiumLibraryInternalError(PdfiumInternalError::PasswordError)) => {}
+        Err(e) => return Err(format!("Failed to open the PDF: {}", e)),
+    }
+
+    candidates
+        .iter()
+        .find(|candidate| pdfium.load_pdf_from_file(path, Some(candidate)).is_ok())
+        .map(|candidate| Some(candidate.clone()))
+        .ok_or_else(|| "Encrypted, no valid password".to_string())
+}
+
+const TEXT_FONT_SIZE: f32 = 10.0;
+const TEXT_LINE_HEIGHT: f32 = 13.0;
+const TEXT_MARGIN: f32 = 56.0; // roughly 2 cm
+const TEXT_CHARS_PER_LINE: usize = 90; // fits A4 in a 10pt monospace font
+
+/// Lay plain text out on A4 pages and save it as a PDF, for documents that do not come with a
+/// PDF of their own (e.g. the body of an e-mail).
+///
+/// Not async so should be run on a blocking thread pool
+pub fn text_to_pdf(text: &str) -> Result<Vec<u8>, PdfiumError> {
+    let pdfium = bind_pdfium();
+    let mut document = pdfium.create_new_pdf()?;
+
+    // a monospace font makes the line wrapping predictable
+    let font = document.fonts_mut().courier();
+
+    let paper = PdfPagePaperSize::a4();
+    let page_height = paper.height().value;
+
+    for page_text in text_pages(text) {
+        let mut page = document.pages_mut().create_page_at_end(paper)?;
+
+        for (index, line) in page_text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let y = page_height - TEXT_MARGIN - (index as f32 + 1.0) * TEXT_LINE_HEIGHT;
+
+            page.objects_mut().create_text_object(
+                PdfPoints::new(TEXT_MARGIN),
+                PdfPoints::new(y),
+                line,
+                font,
+                PdfPoints::new(TEXT_FONT_SIZE),
+            )?;
+        }
+    }
+
+    document.save_to_bytes()
+}
+
+/// Split text into the pages [`text_to_pdf`] lays it out on, wrapped the same way, so the text
+/// of a page matches the rendered page.
+pub fn text_pages(text: &str) -> Vec<String> {
+    let page_height = PdfPagePaperSize::a4().height().value;
+    let lines_per_page = ((page_height - 2.0 * TEXT_MARGIN) / TEXT_LINE_HEIGHT) as usize;
+
+    let lines = wrap_lines(text, TEXT_CHARS_PER_LINE);
+
+    // an empty text still gets a (blank) page
+    if lines.is_empty() {
+        return vec![String::new()];
+    }
+
+    lines
+        .chunks(lines_per_page)
+        .map(|page_lines| page_lines.join("\n"))
+        .collect()
+}
+
+/// Break text into lines of at most `width` characters, preferring to break at whitespace.
+fn wrap_lines(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for paragraph in text.replace('\t', "    ").lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split(' ') {
+            let mut word = word.to_string();
+
+            // words longer than a whole line are split wherever they overflow
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let split = word
+                    .char_indices()
+                    .nth(width)
+                    .map_or(word.len(), |(i, _)| i);
+                lines.push(word[..split].to_string());
+                word = word[split..].to_string();
+            }
+
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+
+        lines.push(line);
+    }
+
+    // drop trailing blank lines so they do not produce empty pages
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
 
-    Ok(bytes)
+/// How many typos a fuzzy match allows in a word, fewer in short words.
+pub fn fuzzy_distance(word: &str) -> u8 {
+    if word.len() >= 5 { 2 } else { 1 }
 }
 
 #[allow(dead_code)]
@@ -71,8 +237,7 @@ pub fn simple_fuzzy_query(title: Field, body: Field, input: &str) -> tantivy::Re
         return Ok(BooleanQuery::new(vec![]));
     }
 
-    // choose edit distance based on length
-    let dist = if q.len() >= 5 { 2 } else { 1 };
+    let dist = fuzzy_distance(&q);
 
     let t_title = Term::from_field_text(title, &q);
     let t_body = Term::from_field_text(body, &q);
@@ -0,0 +1,237 @@
+// The single dedicated thread that works through the ingestion queue. CPU-intensive work
+// (pdfium rendering, OCR, PNG encoding) runs here so the request handlers stay responsive.
+
+use std::error::Error;
+use std::sync::{Arc, LazyLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use diesel::dsl::{IntervalDsl, now};
+use diesel::sql_types::Text;
+use diesel::{
+    BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, OptionalExtension,
+    QueryDsl, RunQueryDsl, SelectableHelper,
+};
+
+use crate::events::Event;
+use crate::models::{Task, TaskStatus};
+use crate::schema::tasks;
+use crate::{AppState, PgPool, ingest};
+
+/// How often the queue is checked when nobody notified the worker, e.g. for tasks queued by
+/// another process.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a task can go without a heartbeat from the process running it before it is taken
+/// to be interrupted, e.g. by a crash, and queued again.
+const LEASE: Duration = Duration::from_secs(60);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Identifies this process in `tasks.claimed_by`, so other processes leave its tasks alone.
+static WORKER_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+
+pub fn spawn(state: Arc<AppState>) -> JoinHandle<()> {
+    let pool = state.db_pool.clone();
+
+    // on its own thread, the worker thread is busy with OCR for minutes at a time
+    std::thread::Builder::new()
+        .name("ingest-heartbeat".to_string())
+        .spawn(move || {
+            loop {
+                std::thread::sleep(HEARTBEAT_INTERVAL);
+
+                if let Err(e) = heartbeat(&pool) {
+                    println!("Failed to renew the lease of running tasks: {}", e);
+                }
+            }
+        })
+        .expect("Failed to spawn the heartbeat thread");
+
+    std::thread::Builder::new()
+        .name("ingest-worker".to_string())
+        .spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("Failed to build the worker runtime");
+
+            // Tasks are spawned locally so that a panic during ingestion fails the task
+            // instead of taking the worker down with it.
+            let local = tokio::task::LocalSet::new();
+            local.block_on(&runtime, run(state));
+        })
+        .expect("Failed to spawn the ingestion worker")
+}
+
+async fn run(state: Arc<AppState>) {
+    loop {
+        match requeue_interrupted(&state.db_pool) {
+            Ok(0) => {}
+            Ok(count) => println!("Re-queued {} interrupted task(s)", count),
+            Err(e) => println!("Failed to re-queue interrupted tasks: {}", e),
+        }
+
+        match next_task(&state.db_pool) {
+            Ok(Some(task)) => {
+                state.events.publish(Event::TaskUpdated {
+                    task: Box::new(task.clone()),
+                });
+                process(&state, task).await
+            }
+            Ok(None) => {
+                tokio::select! {
+                    _ = state.task_notify.notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(e) => {
+                println!("Failed to fetch the next task: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn process(state: &Arc<AppState>, task: Task) {
+    println!("Ingesting `{}` (task {})", task.filename, task.id);
+
+    let file_path = task.file_path.clone();
+    let task_id = task.id.clone();
+
+    let result = tokio::task::spawn_local(ingest::ingest(Arc::clone(state), task)).await;
+
+    let (status, error) = match result {
+        Ok(Ok(())) => (TaskStatus::Done, None),
+        Ok(Err(e)) => (TaskStatus::Failed, Some(e.to_string())),
+        Err(e) => (
+            TaskStatus::Failed,
+            Some(format!("Ingestion panicked: {}", e)),
+        ),
+    };
+
+    match &error {
+        Some(e) => println!("Task {} failed: {}", task_id, e),
+        None => println!("Task {} done", task_id),
+    }
+
+    match finish_task(&state.db_pool, &task_id, status, error) {
+        Ok(task) => state.events.publish(Event::TaskUpdated {
+            task: Box::new(task),
+        }),
+        Err(e) => println!("Failed to record the result of task {}: {}", task_id, e),
+    }
+
+    if let Err(e) = tokio::fs::remove_file(&file_path).await {
+        println!("Failed to remove staged upload {}: {}", file_path, e);
+    }
+}
+
+fn in_progress() -> Vec<&'static str> {
+    TaskStatus::IN_PROGRESS
+        .iter()
+        .map(TaskStatus::as_str)
+        .collect()
+}
+
+/// Keep the tasks this process is running from being taken for interrupted.
+fn heartbeat(pool: &PgPool) -> Result<usize, Box<dyn Error>> {
+    let mut conn = pool.get()?;
+
+    let running = tasks::table.filter(
+        tasks::claimed_by
+            .eq(WORKER_ID.as_str())
+            .and(tasks::status.eq_any(in_progress())),
+    );
+
+    let count = diesel::update(running)
+        .set(tasks::heartbeat_at.eq(now.nullable()))
+        .execute(&mut conn)?;
+
+    Ok(count)
+}
+
+/// Tasks whose process stopped renewing their lease were interrupted mid-way, so they start
+/// over. Tasks of processes that are still running are left alone.
+fn requeue_interrupted(pool: &PgPool) -> Result<usize, Box<dyn Error>> {
+    let mut conn = pool.get()?;
+
+    let expired = (now - (LEASE.as_secs() as i64).seconds()).nullable();
+
+    let interrupted = tasks::table.filter(
+        tasks::status.eq_any(in_progress()).and(
+            tasks::heartbeat_at
+                .is_null()
+                .or(tasks::heartbeat_at.lt(expired)),
+        ),
+    );
+
+    let count = diesel::update(interrupted)
+        .set((
+            tasks::status.eq(TaskStatus::Queued.as_str()),
+            tasks::started_at.eq(None::<chrono::NaiveDateTime>),
+            tasks::claimed_by.eq(None::<String>),
+            tasks::heartbeat_at.eq(None::<chrono::NaiveDateTime>),
+        ))
+        .execute(&mut conn)?;
+
+    Ok(count)
+}
+
+/// Claim the oldest queued task by moving it to the first stage. Claiming is one statement
+/// and skips rows another process is claiming, so no two processes get the same task.
+fn next_task(pool: &PgPool) -> Result<Option<Task>, Box<dyn Error>> {
+    let mut conn = pool.get()?;
+
+    let task = diesel::sql_query(
+        "UPDATE tasks
+         SET status = $1, started_at = NOW(), claimed_by = $2, heartbeat_at = NOW()
+         WHERE id = (
+           SELECT id FROM tasks
+           WHERE status = $3
+           ORDER BY created_at
+           LIMIT 1
+           FOR UPDATE SKIP LOCKED
+         )
+         RETURNING *",
+    )
+    .bind::<Text, _>(TaskStatus::Extracting.as_str())
+    .bind::<Text, _>(WORKER_ID.as_str())
+    .bind::<Text, _>(TaskStatus::Queued.as_str())
+    .get_result::<Task>(&mut conn)
+    .optional()?;
+
+    Ok(task)
+}
+
+/// Record which stage of the pipeline a task has reached.
+pub fn update_status(pool: &PgPool, id: &str, status: TaskStatus) -> Result<Task, Box<dyn Error>> {
+    let mut conn = pool.get()?;
+
+    let task = diesel::update(tasks::table.find(id))
+        .set(tasks::status.eq(status.as_str()))
+        .returning(Task::as_returning())
+        .get_result(&mut conn)?;
+
+    Ok(task)
+}
+
+fn finish_task(
+    pool: &PgPool,
+    id: &str,
+    status: TaskStatus,
+    error: Option<String>,
+) -> Result<Task, Box<dyn Error>> {
+    let mut conn = pool.get()?;
+
+    let task = diesel::update(tasks::table.find(id))
+        .set((
+            tasks::status.eq(status.as_str()),
+            tasks::error.eq(error),
+            tasks::finished_at.eq(now.nullable()),
+            tasks::password.eq(None::<String>),
+        ))
+        .returning(Task::as_returning())
+        .get_result(&mut conn)?;
+
+    Ok(task)
+}